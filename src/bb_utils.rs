@@ -1,5 +1,20 @@
 
-use chess::{BitBoard, EMPTY, File};
+use chess::{BitBoard, Color, EMPTY, File, Square};
+
+// Moves a king needs to walk between the squares
+pub fn square_distance(from : Square, to : Square) -> i32 {
+    let file_distance = (from.get_file().to_index() as i32 - to.get_file().to_index() as i32).abs();
+    let rank_distance = (from.get_rank().to_index() as i32 - to.get_rank().to_index() as i32).abs();
+    i32::max(file_distance, rank_distance)
+}
+
+// Ranks counted from color's side of the board
+pub fn relative_rank(color : Color, rank : usize) -> usize {
+    match color {
+        Color::White => rank,
+        Color::Black => 7 - rank,
+    }
+}
 
 pub struct BitBoardUtils {
     pub file_mask : [BitBoard; 8],
@@ -20,6 +35,26 @@ impl BitBoardUtils {
         self.file_mask[file.to_index()]
     }
 
+    // Every rank further up the board than rank, as color sees it
+    pub fn ranks_in_front(&self, color : Color, rank : usize) -> BitBoard {
+        let mut mask = EMPTY;
+        match color {
+            Color::White => for in_front in rank + 1..8 { mask |= self.rank_mask[in_front]; },
+            Color::Black => for in_front in 0..rank { mask |= self.rank_mask[in_front]; },
+        }
+        mask
+    }
+
+    // Every square attacked by one of pawns. Pawns on the edge files mustn't wrap around to the other side
+    pub fn pawn_attacks(&self, pawns : BitBoard, color : Color) -> BitBoard {
+        let not_a_file = pawns & !self.file_mask[File::A.to_index()];
+        let not_h_file = pawns & !self.file_mask[File::H.to_index()];
+        match color {
+            Color::White => BitBoard(not_a_file.0 << 7) | BitBoard(not_h_file.0 << 9),
+            Color::Black => BitBoard(not_a_file.0 >> 9) | BitBoard(not_h_file.0 >> 7),
+        }
+    }
+
     pub fn new() -> BitBoardUtils {
         let mut ret = BitBoardUtils {
             file_mask :  [EMPTY; 8],
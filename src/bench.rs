@@ -0,0 +1,43 @@
+// Searches a fixed set of positions to a fixed depth. The total node count is a signature of the search,
+// any change to it means the search behaves differently.
+use chess::Board;
+use std::str::FromStr;
+use std::time::SystemTime;
+use crate::search::Search;
+use crate::transpo::TranspoTable;
+
+pub const DEFAULT_BENCH_DEPTH : u32 = 8;
+
+const BENCH_FENS : [&str; 12] = [
+    "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+    "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+    "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+    "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+    "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+    "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+    "r1bqkb1r/pppp1ppp/2n2n2/4p3/2B1P3/5N2/PPPP1PPP/RNBQK2R w KQkq - 4 4",
+    "rnbqkb1r/pp2pppp/3p1n2/8/3NP3/8/PPP2PPP/RNBQKB1R w KQkq - 1 5",
+    "r2q1rk1/pb1nbppp/1p2pn2/2pp4/2PP4/1PN1PN2/PB2BPPP/R2Q1RK1 w - - 0 10",
+    "2r3k1/pp3ppp/4p3/3pP3/3P4/P4N2/1P3PPP/2R3K1 b - - 0 25",
+    "8/5pk1/6p1/8/3K4/6P1/5P2/8 w - - 0 40",
+    "8/8/4k3/8/2R5/8/4K3/3r4 w - - 0 60",
+];
+
+pub fn bench(depth : u32) {
+    let start = SystemTime::now();
+    let mut total_nodes = 0;
+    for fen in BENCH_FENS {
+        let board = Board::from_str(fen).unwrap();
+        let tt = TranspoTable::new();
+        let mut search = Search::new();
+        search.set_cfg_depth(depth);
+        search.get_best_move(&board, &tt);
+        total_nodes += search.get_nodes();
+    }
+    let duration_millis = u128::max(start.elapsed().unwrap().as_millis(), 1);
+    let nps = (total_nodes as f64 / (duration_millis as f64 / 1000.0)) as u64;
+
+    println!();
+    println!("Nodes searched: {total_nodes}");
+    println!("Took {}s. nps {nps}", duration_millis as f64 / 1000.0);
+}
@@ -0,0 +1,323 @@
+// Endgames the general eval gets wrong
+// They're recognised by their material signature, the pieces each side has left. Some have a dedicated
+// evaluator that replaces the general eval, others only scale its endgame score down when the material
+// is much harder to win with than it looks.
+use chess::{BitBoard, Board, Color, File, Piece, Rank, Square, ALL_SQUARES, EMPTY, get_file};
+use std::sync::OnceLock;
+use crate::bb_utils::square_distance;
+use crate::evaluation::NAIVE_PIECE_VAL;
+use crate::kpk;
+
+// Above any normal eval and below every mate score, so the search heads for the win and still finds the mate
+pub const KNOWN_WIN : i32 = 10000;
+// Scale factors are out of this, it keeps the endgame score as it is
+pub const SCALE_NORMAL : i32 = 64;
+// Only bishops on opposite colours and pawns, most of these are drawn however many pawns are up
+const OPPOSITE_BISHOPS_SCALE : i32 = 22;
+// Other pieces on the board give the stronger side more to work with
+const OPPOSITE_BISHOPS_WITH_PIECES_SCALE : i32 = 46;
+// Driving the lone king to the edge and bringing our king up to help mate it
+const PUSH_TO_EDGE_WEIGHT : i32 = 20;
+const PUSH_CLOSE_WEIGHT : i32 = 10;
+// Bishop and knight can only mate in a corner the bishop covers
+const PUSH_TO_CORNER_WEIGHT : i32 = 15;
+const MAX_SIGNATURE_PIECES : u32 = 4;
+const PIECE_ORDER : [Piece; 5] = [Piece::Pawn, Piece::Knight, Piece::Bishop, Piece::Rook, Piece::Queen];
+
+// Evaluators score the position for strong, the side named first in the signature
+type EndgameEval = fn(&Board, Color) -> i32;
+
+struct Endgame {
+    signature : &'static str,
+    eval : EndgameEval,
+}
+
+const ENDGAMES : [Endgame; 9] = [
+    Endgame { signature : "KQK", eval : mate_bare_king },
+    Endgame { signature : "KRK", eval : mate_bare_king },
+    Endgame { signature : "KBNK", eval : bishop_knight_mate },
+    Endgame { signature : "KPK", eval : king_pawn_king },
+    Endgame { signature : "KRKP", eval : rook_against_pawn },
+    // Not enough material to mate
+    Endgame { signature : "KK", eval : draw },
+    Endgame { signature : "KNK", eval : draw },
+    Endgame { signature : "KBK", eval : draw },
+    Endgame { signature : "KNNK", eval : draw },
+];
+
+// ENDGAMES by material key, once for each side being the strong side
+static ENDGAME_KEYS : OnceLock<Vec<(u64, Color, EndgameEval)>> = OnceLock::new();
+
+// Four bits for the count of each piece but the king, white in the low bits
+fn material_key(counts : &[[u64; 5]; 2]) -> u64 {
+    let mut key = 0;
+    for (color, color_counts) in counts.iter().enumerate() {
+        for (piece, count) in color_counts.iter().enumerate() {
+            key |= count << (4 * (color * PIECE_ORDER.len() + piece));
+        }
+    }
+    key
+}
+
+fn board_material_key(board : &Board) -> u64 {
+    let mut counts = [[0; 5]; 2];
+    for color in [Color::White, Color::Black] {
+        for (i, piece) in PIECE_ORDER.iter().enumerate() {
+            counts[color.to_index()][i] = (board.pieces(*piece) & board.color_combined(color)).popcnt() as u64;
+        }
+    }
+    material_key(&counts)
+}
+
+// "KRKP" is a king and rook for strong against a king and pawn
+fn signature_key(signature : &str, strong : Color) -> u64 {
+    let mut counts = [[0; 5]; 2];
+    let (strong_pieces, weak_pieces) = signature[1..].split_once('K').unwrap();
+    for (color, pieces) in [(strong, strong_pieces), (!strong, weak_pieces)] {
+        for piece in pieces.chars() {
+            let i = "PNBRQ".find(piece).unwrap();
+            counts[color.to_index()][i] += 1;
+        }
+    }
+    material_key(&counts)
+}
+
+fn endgame_keys() -> Vec<(u64, Color, EndgameEval)> {
+    let mut keys = Vec::new();
+    for endgame in ENDGAMES.iter() {
+        for strong in [Color::White, Color::Black] {
+            keys.push((signature_key(endgame.signature, strong), strong, endgame.eval));
+        }
+    }
+    keys
+}
+
+fn non_pawn_material(board : &Board, color : Color) -> BitBoard {
+    board.color_combined(color) & !board.pieces(Piece::Pawn) & !board.pieces(Piece::King)
+}
+
+fn material_value(board : &Board, color : Color) -> i32 {
+    let mut value = 0;
+    for piece in PIECE_ORDER {
+        value += NAIVE_PIECE_VAL[piece.to_index()] * (board.pieces(piece) & board.color_combined(color)).popcnt() as i32;
+    }
+    value
+}
+
+// Flips the board for black so every evaluator can work as if strong were white
+fn relative_square(color : Color, square : Square) -> Square {
+    match color {
+        Color::White => square,
+        Color::Black => ALL_SQUARES[square.to_index() ^ 56],
+    }
+}
+
+// How far a square is from the centre, 0 to 6
+fn edge_closeness(square : Square) -> i32 {
+    let file = square.get_file().to_index() as i32;
+    let rank = square.get_rank().to_index() as i32;
+    i32::max(3 - file, file - 4) + i32::max(3 - rank, rank - 4)
+}
+
+// 0 for dark squares, 1 for light
+fn square_colour(square : Square) -> usize {
+    (square.get_file().to_index() + square.get_rank().to_index()) % 2
+}
+
+fn draw(_board : &Board, _strong : Color) -> i32 {
+    0
+}
+
+// A queen or rook, maybe with more, against a lone king. Mating just needs the king on the edge
+fn mate_bare_king(board : &Board, strong : Color) -> i32 {
+    let strong_king = board.king_square(strong);
+    let weak_king = board.king_square(!strong);
+    KNOWN_WIN + material_value(board, strong) + PUSH_TO_EDGE_WEIGHT * edge_closeness(weak_king)
+        + PUSH_CLOSE_WEIGHT * (7 - square_distance(strong_king, weak_king))
+}
+
+fn bishop_knight_mate(board : &Board, strong : Color) -> i32 {
+    let strong_king = board.king_square(strong);
+    let weak_king = board.king_square(!strong);
+    let bishop = (board.pieces(Piece::Bishop) & board.color_combined(strong)).to_square();
+    let corners = if square_colour(bishop) == square_colour(Square::A1) { [Square::A1, Square::H8] } else { [Square::A8, Square::H1] };
+    let corner_distance = corners.iter().map(|corner| {
+        (weak_king.get_file().to_index() as i32 - corner.get_file().to_index() as i32).abs()
+            + (weak_king.get_rank().to_index() as i32 - corner.get_rank().to_index() as i32).abs()
+    }).min().unwrap();
+    KNOWN_WIN + material_value(board, strong) + PUSH_TO_CORNER_WEIGHT * (14 - corner_distance)
+        + PUSH_CLOSE_WEIGHT * (7 - square_distance(strong_king, weak_king))
+}
+
+fn king_pawn_king(board : &Board, strong : Color) -> i32 {
+    let pawn = relative_square(strong, board.pieces(Piece::Pawn).to_square());
+    let strong_king = relative_square(strong, board.king_square(strong));
+    let weak_king = relative_square(strong, board.king_square(!strong));
+    if !kpk::probe(board.side_to_move() == strong, strong_king, pawn, weak_king) {
+        return 0
+    }
+    KNOWN_WIN + NAIVE_PIECE_VAL[Piece::Pawn.to_index()] + pawn.get_rank().to_index() as i32
+}
+
+// Usually a win for the rook, unless the pawn is far up the board with its king next to it
+fn rook_against_pawn(board : &Board, strong : Color) -> i32 {
+    let to_move = board.side_to_move();
+    let strong_king = relative_square(strong, board.king_square(strong));
+    let weak_king = relative_square(strong, board.king_square(!strong));
+    let rook = relative_square(strong, board.pieces(Piece::Rook).to_square());
+    let pawn = relative_square(strong, board.pieces(Piece::Pawn).to_square());
+    // The pawn moves down the board from here on
+    let promotion_square = Square::make_square(Rank::First, pawn.get_file());
+    let below_pawn = pawn.ubackward(Color::White);
+    let rook_value = NAIVE_PIECE_VAL[Piece::Rook.to_index()];
+
+    // Our king is in front of the pawn
+    if strong_king.get_file() == pawn.get_file() && strong_king.get_rank().to_index() < pawn.get_rank().to_index() {
+        return rook_value - square_distance(strong_king, pawn)
+    }
+    // Their king is too far away to help the pawn
+    if square_distance(weak_king, pawn) >= 3 + (to_move != strong) as i32 && square_distance(weak_king, rook) >= 3 {
+        return rook_value - square_distance(strong_king, pawn)
+    }
+    // The pawn is close to promoting with its king beside it, and our king is too far away to help
+    if weak_king.get_rank().to_index() <= 2 && square_distance(weak_king, pawn) == 1
+        && strong_king.get_rank().to_index() >= 3 && square_distance(strong_king, pawn) > 2 + (to_move == strong) as i32 {
+        return 80 - 8 * square_distance(strong_king, pawn)
+    }
+    200 - 8 * (square_distance(strong_king, below_pawn) - square_distance(weak_king, below_pawn)
+        - square_distance(pawn, promotion_square))
+}
+
+// A dedicated evaluation if the position has one, from white's point of view
+pub fn evaluate(board : &Board) -> Option<i32> {
+    // No signature has more than this many pieces, most positions don't need their key worked out
+    let found = if board.combined().popcnt() <= MAX_SIGNATURE_PIECES {
+        let key = board_material_key(board);
+        ENDGAME_KEYS.get_or_init(endgame_keys).iter().find(|(endgame_key, _, _)| *endgame_key == key)
+    } else {
+        None
+    };
+    let (strong, eval) = match found {
+        Some((_, strong, eval)) => (*strong, *eval),
+        None => {
+            // Any other mating material against a lone king
+            let strong = if board.color_combined(Color::White).popcnt() > 1 { Color::White } else { Color::Black };
+            let heavy_pieces = board.pieces(Piece::Rook) | board.pieces(Piece::Queen);
+            if board.color_combined(!strong).popcnt() != 1 || (heavy_pieces & board.color_combined(strong)) == EMPTY {
+                return None
+            }
+            (strong, mate_bare_king as EndgameEval)
+        }
+    };
+    let value = eval(board, strong);
+    Some(if strong == Color::White { value } else { -value })
+}
+
+// All of color's pawns are on one rook file, and the defending king is in the corner in front of them
+fn rook_pawns_blocked(board : &Board, color : Color) -> bool {
+    let pawns = board.pieces(Piece::Pawn) & board.color_combined(color);
+    let Some(file) = [File::A, File::H].into_iter().find(|file| (pawns & !get_file(*file)) == EMPTY) else {
+        return false
+    };
+    let promotion_square = relative_square(color, Square::make_square(Rank::Eighth, file));
+    if square_distance(board.king_square(!color), promotion_square) > 1 {
+        return false
+    }
+    // A bishop that covers the promotion square can drive the king out
+    for bishop in board.pieces(Piece::Bishop) & board.color_combined(color) {
+        if square_colour(bishop) == square_colour(promotion_square) {
+            return false
+        }
+    }
+    true
+}
+
+// How much of the endgame score strong gets to keep, out of SCALE_NORMAL
+pub fn scale_factor(board : &Board, strong : Color) -> i32 {
+    let strong_pieces = non_pawn_material(board, strong);
+    let weak_pieces = non_pawn_material(board, !strong);
+    let bishops = board.pieces(Piece::Bishop);
+
+    // Wrong rook pawn. Only pawns, or a bishop of the wrong colour, against a lone king
+    if board.pieces(Piece::Pawn) & board.color_combined(strong) != EMPTY && (strong_pieces & !bishops) == EMPTY
+        && board.color_combined(!strong).popcnt() == 1 && rook_pawns_blocked(board, strong) {
+        return 0
+    }
+
+    let strong_bishops = strong_pieces & bishops;
+    let weak_bishops = weak_pieces & bishops;
+    if strong_bishops.popcnt() == 1 && weak_bishops.popcnt() == 1
+        && square_colour(strong_bishops.to_square()) != square_colour(weak_bishops.to_square()) {
+        if strong_pieces.popcnt() == 1 && weak_pieces.popcnt() == 1 {
+            return OPPOSITE_BISHOPS_SCALE
+        }
+        return OPPOSITE_BISHOPS_WITH_PIECES_SCALE
+    }
+    SCALE_NORMAL
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn evaluate_fen(fen : &str) -> Option<i32> {
+        evaluate(&Board::from_str(fen).unwrap())
+    }
+
+    fn scale_fen(fen : &str, strong : Color) -> i32 {
+        scale_factor(&Board::from_str(fen).unwrap(), strong)
+    }
+
+    #[test]
+    fn signature_dispatch() {
+        // KQK and KRK, for either side
+        assert!(evaluate_fen("4k3/8/8/8/8/8/8/3QK3 w - - 0 1").unwrap() > KNOWN_WIN);
+        assert!(evaluate_fen("4k3/8/8/8/8/8/r7/4K3 w - - 0 1").unwrap() < -KNOWN_WIN);
+        assert!(evaluate_fen("4k3/8/8/8/8/8/8/2BNK3 w - - 0 1").unwrap() > KNOWN_WIN);
+        // Black's king is too far from its pawn to save it
+        assert_eq!(evaluate_fen("7k/8/8/8/p7/8/8/4K1R1 w - - 0 1"), Some(496));
+        // Not enough material to mate
+        for fen in ["4k3/8/8/8/8/8/8/4K3 w - - 0 1", "4k3/8/8/8/8/8/8/1N2K3 w - - 0 1",
+            "4k3/8/8/8/8/8/8/2b1K3 w - - 0 1", "4k3/8/8/8/8/8/8/1NN1K3 w - - 0 1"] {
+            assert_eq!(evaluate_fen(fen), Some(0));
+        }
+        // A lone king against more than the signatures cover
+        assert!(evaluate_fen("4k3/8/8/8/8/8/PPP5/R3K3 w - - 0 1").unwrap() > KNOWN_WIN);
+        // Left to the general eval
+        assert_eq!(evaluate_fen("4k3/8/8/8/8/8/8/r2QK3 w - - 0 1"), None);
+        assert_eq!(evaluate_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"), None);
+    }
+
+    #[test]
+    fn bishop_knight_corner() {
+        // Dark squared bishop, so the mate is in a1 or h8
+        let dark_corner = evaluate_fen("8/8/8/8/7N/2K5/7B/k7 w - - 0 1").unwrap();
+        let light_corner = evaluate_fen("k7/8/2K5/8/7N/8/7B/8 w - - 0 1").unwrap();
+        assert!(dark_corner > light_corner);
+        // With a light squared bishop it's the other way round
+        let dark_corner = evaluate_fen("8/8/8/8/7N/2K5/6B1/k7 w - - 0 1").unwrap();
+        let light_corner = evaluate_fen("k7/8/2K5/8/7N/8/6B1/8 w - - 0 1").unwrap();
+        assert!(light_corner > dark_corner);
+    }
+
+    #[test]
+    fn wrong_rook_pawn() {
+        // The light squared bishop can't drive the king out of h8
+        assert_eq!(scale_fen("7k/8/8/7P/8/8/4B3/4K3 w - - 0 1", Color::White), 0);
+        assert_eq!(scale_fen("7k/8/8/7P/8/8/8/4K3 w - - 0 1", Color::White), 0);
+        assert_eq!(scale_fen("7k/8/8/7P/8/8/5B2/4K3 w - - 0 1", Color::White), SCALE_NORMAL);
+        // The king has to be in front of the pawn
+        assert_eq!(scale_fen("8/8/4k3/7P/8/8/4B3/4K3 w - - 0 1", Color::White), SCALE_NORMAL);
+        // Same for black, promoting on a1
+        assert_eq!(scale_fen("4k3/8/8/8/p7/8/8/1K6 w - - 0 1", Color::Black), 0);
+    }
+
+    #[test]
+    fn opposite_bishops() {
+        assert_eq!(scale_fen("4k3/5p2/8/2b5/8/3B4/4PP2/4K3 w - - 0 1", Color::White), OPPOSITE_BISHOPS_SCALE);
+        assert_eq!(scale_fen("4k3/5p2/8/2b2n2/8/3B1N2/4PP2/4K3 w - - 0 1", Color::White), OPPOSITE_BISHOPS_WITH_PIECES_SCALE);
+        // Bishops on the same colour
+        assert_eq!(scale_fen("4k3/5p2/8/8/2b5/3B4/4PP2/4K3 w - - 0 1", Color::White), SCALE_NORMAL);
+    }
+}
@@ -0,0 +1,157 @@
+// A simple front end to the engine for programs using artemis as a library.
+// The uci loop drives Search directly so it can run it on another thread, this is the blocking version.
+use chess::{Board, ChessMove};
+use std::collections::TryReserveError;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use vampirc_uci::{UciSearchControl, UciTimeControl};
+use crate::evaluation::Evaluator;
+use crate::history::PositionHistory;
+use crate::pawns::PawnTable;
+use crate::search::Search;
+use crate::transpo::TranspoTable;
+
+// Used when a search is given no limits at all
+pub const DEFAULT_SEARCH_DEPTH : u32 = 8;
+// Longer than any game, and always fits in vampirc's Duration
+const MAX_MOVE_TIME : Duration = Duration::from_secs(365 * 24 * 60 * 60);
+
+// Depth and node limited searches don't look at the clock, so move_time only applies on its own
+#[derive(Clone, Default)]
+pub struct SearchLimits {
+    pub depth : Option<u32>,
+    pub nodes : Option<u64>,
+    pub move_time : Option<Duration>,
+}
+
+#[derive(Clone)]
+pub struct SearchInfo {
+    pub best_move : ChessMove,
+    pub ponder_move : Option<ChessMove>,
+    // Centipawns from the side to move's point of view, see evaluation::eval_is_mate for mate scores
+    pub score : i32,
+    pub pv : Vec<ChessMove>,
+    pub nodes : u64,
+}
+
+pub struct Engine {
+    board : Board,
+    history : PositionHistory,
+    tt : TranspoTable,
+    evaluator : Evaluator,
+    // Each search thread's pawn table, kept from one search to the next
+    pawn_tables : Arc<Mutex<Vec<PawnTable>>>,
+    num_threads : usize,
+    stop : Arc<AtomicBool>,
+}
+
+impl Default for Engine {
+    fn default() -> Engine {
+        Engine::new()
+    }
+}
+
+impl Engine {
+    pub fn new() -> Engine {
+        let board = Board::default();
+        Engine {
+            board : board,
+            history : PositionHistory::new(&board, 0),
+            tt : TranspoTable::new(),
+            evaluator : Evaluator::new(),
+            pawn_tables : Arc::new(Mutex::new(Vec::new())),
+            num_threads : 1,
+            stop : Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    // Fails without changing the size if the memory can't be allocated
+    pub fn set_hash_size_mb(&mut self, size_mb : u64) -> Result<(), TryReserveError> {
+        self.tt.set_size_mb(size_mb)
+    }
+
+    pub fn set_threads(&mut self, num_threads : usize) {
+        self.num_threads = num_threads;
+    }
+
+    // Forget everything learned from earlier searches
+    pub fn new_game(&mut self) {
+        self.tt.clear();
+        self.board = Board::default();
+        self.history = PositionHistory::new(&self.board, 0);
+    }
+
+    // fen is the start position, None for the normal one. The moves are played from it in order
+    pub fn set_position(&mut self, fen : Option<&str>, moves : &[ChessMove]) -> Result<(), chess::Error> {
+        let (mut board, halfmove_clock) = match fen {
+            Some(fen) => (Board::from_str(fen)?, PositionHistory::halfmove_clock_from_fen(fen)),
+            None => (Board::default(), 0),
+        };
+        let mut history = PositionHistory::new(&board, halfmove_clock);
+        for chess_move in moves {
+            if !board.legal(*chess_move) {
+                return Err(chess::Error::InvalidUciMove)
+            }
+            history.push_move(&board, *chess_move);
+            board = board.make_move_new(*chess_move);
+        }
+        self.board = board;
+        self.history = history;
+        Ok(())
+    }
+
+    pub fn board(&self) -> &Board {
+        &self.board
+    }
+
+    // Setting the flag from another thread ends the current search early
+    pub fn stop_flag(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.stop)
+    }
+
+    // Blocks until the search is done. Nothing is printed
+    pub fn search(&mut self, limits : &SearchLimits) -> SearchInfo {
+        self.stop.store(false, Ordering::Relaxed);
+        let mut search = Search::new();
+        search.set_report_info(false);
+        search.set_stop_flag(Arc::clone(&self.stop));
+        search.set_pawn_tables(Arc::clone(&self.pawn_tables));
+        search.set_history(self.history.clone());
+        search.set_threads(self.num_threads);
+
+        if limits.nodes.is_some() {
+            search.set_search_controls(UciSearchControl {
+                search_moves : Vec::new(),
+                mate : None,
+                depth : None,
+                nodes : limits.nodes,
+            });
+        }
+        if let Some(depth) = limits.depth {
+            search.set_cfg_depth(depth);
+        }
+        if let Some(move_time) = limits.move_time {
+            let move_time = vampirc_uci::Duration::from_std(move_time.min(MAX_MOVE_TIME)).expect("MAX_MOVE_TIME is in range");
+            search.set_time_controls(UciTimeControl::MoveTime(move_time));
+            search.set_move_overhead(Duration::new(0, 0));
+        } else if limits.depth.is_none() && limits.nodes.is_none() {
+            search.set_cfg_depth(DEFAULT_SEARCH_DEPTH);
+        }
+
+        let best_move = search.get_best_move(&self.board, &self.tt);
+        SearchInfo {
+            best_move : best_move,
+            ponder_move : search.get_ponder_move(),
+            score : search.get_score(),
+            pv : search.get_pv().to_vec(),
+            nodes : search.get_nodes(),
+        }
+    }
+
+    // The static evaluation of the current position, from the side to move's point of view
+    pub fn evaluate(&mut self) -> i32 {
+        self.evaluator.eval(&self.board, 0)
+    }
+}
@@ -1,104 +1,83 @@
-use chess::{Color, Board, Piece, BoardStatus, NUM_PIECES, BitBoard, File, EMPTY, ALL_FILES};
+use chess::{Color, Board, Piece, BoardStatus, BitBoard, Rank, Square, EMPTY, ALL_FILES, NUM_COLORS, NUM_PIECES, get_adjacent_files,
+    get_bishop_moves, get_king_moves, get_knight_moves, get_rook_moves};
+use std::mem;
 
-use crate::bb_utils::BitBoardUtils;
+use crate::bb_utils::{relative_rank, square_distance, BitBoardUtils};
+use crate::endgame;
+use crate::pawns::PawnTable;
+use crate::pst;
+use crate::score::{self, Score};
 
 pub const NAIVE_PIECE_VAL : [i32; chess::NUM_PIECES] =  [100, 290, 310, 500, 900, i32::MAX];
-const ALL_PIECES : [Piece; chess::NUM_PIECES] = [Piece::Pawn, Piece::Knight, Piece::Bishop, Piece::Rook, Piece::Queen, Piece::King];
 const ALL_PIECES_NO_KING : [Piece; chess::NUM_PIECES-1] = [Piece::Pawn, Piece::Knight, Piece::Bishop, Piece::Rook, Piece::Queen];
-const DOUBLE_PAWN_PENALTY : i32 = 30;
-const ISOLATED_PAWN_PENALTY : i32 = 15;
-const PASSED_PAWN_BONUS : i32 = 25;
-const TEMPO_VALUE : i32 = 5;
-const PAWN_PROTECTOR_BONUS : i32 = 6;
-const PAWN_SECOND_PROTECTOR_BONUS : i32 = 4;
-const KING_ON_SEMI_OPEN : i32 = 7;
-const OPEN_FILE_BONUS : i32 = 20;
-const SEMI_OPEN_FILE_BONUS : i32 = 10;
-
-
-const PAWN_CENTRALIZATION_MIDGAME : [i32; 64] = 
-    [0,0,0,0,0,0,0,0,
-    0,2,2,2,2,2,2,0,
-    0,2,4,4,4,4,2,0,
-    0,2,5,12,12,5,2,0,
-    0,2,5,12,12,5,2,0,
-    0,2,4,4,4,4,2,0,
-    0,2,2,2,2,2,2,0,
-    0,0,0,0,0,0,0,0];
-const KNIGHT_CENTRALIZATION_MIDGAME : [i32; 64] = 
-    [0,0,0,0,0,0,0,0,
-    0,2,2,2,2,2,2,0,
-    0,2,4,4,4,4,2,0,
-    0,2,5,12,12,5,2,0,
-    0,2,5,12,12,5,2,0,
-    0,2,4,4,4,4,2,0,
-    0,2,2,2,2,2,2,0,
-    0,0,0,0,0,0,0,0];
-
-const SLIDER_CENTRALIZATION_MIDGAME : [i32; 64] = 
-    [0,0,0,0,0,0,0,0,
-     0,0,0,0,0,0,0,0,
-     0,0,4,4,4,4,0,0,
-     0,0,4,4,4,4,0,0,
-     0,0,4,4,4,4,0,0,
-     0,0,4,4,4,4,0,0,
-     0,0,0,0,0,0,0,0,
-     0,0,0,0,0,0,0,0,
-    ];
-const KING_CENTRALIZATION_MIDGAME : [i32; 64] = 
-    [0,  0,  0,  0,  0,  0,  0,  0,  
-     0,  0,  0,  0,  0,  0,  0,  0,  
-     0,  0, -8,-10,-10, -8,  0,  0,  
-     0,  0, -10,-16,-16,-10, 0,  0,  
-     0,  0, -10,-16,-16,-10, 0,  0,  
-     0,  0, -8, -10,-10, -8, 0,  0,  
-     0,  0,  0,  0,  0,  0,  0,  0,  
-     0,  0,  0,  0,  0,  0,  0,  0,  
-    ];
-
-
-const ALL_CENTRALIZATION_MIDGAME : [[i32 ; 64]; NUM_PIECES] =
-    [PAWN_CENTRALIZATION_MIDGAME, KNIGHT_CENTRALIZATION_MIDGAME, SLIDER_CENTRALIZATION_MIDGAME, SLIDER_CENTRALIZATION_MIDGAME, SLIDER_CENTRALIZATION_MIDGAME, KING_CENTRALIZATION_MIDGAME];
-const SLIDER_CENTRALIZATION_ENDGAME : [i32; 64] = SLIDER_CENTRALIZATION_MIDGAME;
-const KING_CENTRALIZATION_ENDGAME : [i32; 64] = 
-    [0,  0,  0,  0,  0,  0,  0,  0,  
-     0,  0,  0,  0,  0,  0,  0,  0,  
-     0,  0,  8, 10, 10,  8,  0,  0,  
-     0,  0,  10, 16, 16, 10, 0,  0,  
-     0,  0,  10, 16, 16, 10, 0,  0,  
-     0,  0,  8,  10, 10,  8, 0,  0,  
-     0,  0,  0,  0,  0,  0,  0,  0,  
-     0,  0,  0,  0,  0,  0,  0,  0,  
-    ];
-
-const WHITE_PAWN_POSITION_ENDGAME : [i32; 64] = 
-    [0,0,0,0,0,0,0,0,
-    0,0,0,0,0,0,0,0,
-    0,0,0,20,20,0,0,0,
-    5,5,10,25,25,10,5,5,
-    20,10,20,30,30,20,10,20,
-    25,15,20,20,20,20,15,25,
-    50,50,50,50,50,50,50,50,
-    0,0,0,0,0,0,0,0];
-const BLACK_PAWN_POSITION_ENDGAME : [i32; 64] =
-    [0,0,0,0,0,0,0,0,
-    50,50,50,50,50,50,50,50,
-    25,15,20,20,20,20,15,25,
-    20,10,20,30,30,20,10,20,
-    5,5,10,25,25,10,5,5,
-    0,0,0,20,20,0,0,0,
-    0,0,0,0,0,0,0,0,
-    0,0,0,0,0,0,0,0];
-
-const ALL_WHITE_POSITION_ENDGAME : [[i32 ; 64]; NUM_PIECES] =
-    [WHITE_PAWN_POSITION_ENDGAME, KNIGHT_CENTRALIZATION_MIDGAME, SLIDER_CENTRALIZATION_ENDGAME, SLIDER_CENTRALIZATION_ENDGAME, SLIDER_CENTRALIZATION_ENDGAME, KING_CENTRALIZATION_ENDGAME];
-const ALL_BLACK_POSITION_ENDGAME : [[i32 ; 64]; NUM_PIECES] =
-    [BLACK_PAWN_POSITION_ENDGAME, KNIGHT_CENTRALIZATION_MIDGAME, SLIDER_CENTRALIZATION_ENDGAME, SLIDER_CENTRALIZATION_ENDGAME, SLIDER_CENTRALIZATION_ENDGAME, KING_CENTRALIZATION_ENDGAME];
-
-const MIDDLE_FILES : [File; 6] = [File::B, File::C, File::D, File::E, File::F, File::G];
-
-const EARLY_ENDGAME_CUTOFF : i32 = 2200;
-const LATE_ENDGAME_CUTOFF : i32 = 1600;
+// Material in the eval is tapered, NAIVE_PIECE_VAL is for the search's pruning margins
+const PIECE_VAL : [Score; chess::NUM_PIECES-1] = [Score::new(95, 115), Score::new(295, 285), Score::new(315, 305),
+    Score::new(490, 520), Score::new(900, 930)];
+const TEMPO_VALUE : Score = Score::new(8, 4);
+// King safety only matters while there are pieces around to attack the king, so it's all midgame
+// Attack units per square of the king zone a piece attacks
+const KING_ATTACK_WEIGHT : [i32; NUM_PIECES] = [0, 2, 2, 3, 5, 0];
+// Percent of the danger that counts for this many attackers, a lone attacker can't mate
+const KING_ATTACKERS_SCALE : [i32; 8] = [0, 0, 50, 75, 88, 94, 97, 99];
+// Danger grows with the square of the attack units
+const KING_DANGER_DIVISOR : i32 = 4;
+const MAX_KING_DANGER : i32 = 500;
+// Pawns on the king's file or next to it, one and two ranks in front of the king
+const KING_SHIELD_BONUS : [Score; 2] = [Score::new(12, 0), Score::new(6, 0)];
+const KING_OPEN_FILE_PENALTY : Score = Score::new(25, 0);
+const KING_SEMI_OPEN_FILE_PENALTY : Score = Score::new(12, 0);
+// Enemy pawns on the files around the king, by how many ranks in front of it they are
+const PAWN_STORM_PENALTY : [Score; 5] = [Score::new(0, 0), Score::new(10, 0), Score::new(25, 0),
+    Score::new(15, 0), Score::new(5, 0)];
+const OPEN_FILE_BONUS : Score = Score::new(20, 10);
+const SEMI_OPEN_FILE_BONUS : Score = Score::new(10, 5);
+// Per square a piece can move to, counted from the baseline so a trapped piece gets a penalty
+const MOBILITY_WEIGHT : [Score; NUM_PIECES] = [Score::new(0, 0), Score::new(4, 4), Score::new(5, 5),
+    Score::new(2, 4), Score::new(1, 2), Score::new(0, 0)];
+const MOBILITY_BASELINE : [i32; NUM_PIECES] = [0, 4, 6, 6, 12, 0];
+// A minor piece on the 4th to 6th rank, defended by a pawn, that no enemy pawn can ever chase away
+const KNIGHT_OUTPOST_BONUS : Score = Score::new(25, 15);
+const BISHOP_OUTPOST_BONUS : Score = Score::new(15, 8);
+const OUTPOST_RANKS : [usize; 3] = [3, 4, 5];
+const SEVENTH_RANK : usize = 6;
+const ROOK_ON_SEVENTH_BONUS : Score = Score::new(20, 30);
+const BISHOP_PAIR_BONUS : Score = Score::new(30, 50);
+// Passed pawn races. The bonuses below are per rank a passer has advanced past its third
+const PASSER_MIN_RANK : i32 = 2;
+// Endgame bonus per square of king distance from the square in front of the pawn
+const PASSER_ENEMY_KING_DISTANCE : i32 = 5;
+const PASSER_OWN_KING_DISTANCE : i32 = 2;
+const PASSER_FREE_PATH_BONUS : Score = Score::new(0, 5);
+// Nothing is in the way and no enemy piece covers the path
+const PASSER_UNCONTESTED_PATH_BONUS : Score = Score::new(2, 12);
+const PASSER_BLOCKED_PENALTY : Score = Score::new(2, 6);
+// A pawn the enemy king can't catch with no enemy pieces left is nearly a queen. Closer ones win races
+const UNSTOPPABLE_PAWN_BONUS : i32 = 500;
+const UNSTOPPABLE_PAWN_PER_MOVE : i32 = 20;
+
+// The parts the eval adds up, kept per side so the eval command can show where a score comes from
+#[derive(Clone, Copy)]
+pub enum EvalTerm {
+    Material,
+    PieceSquares,
+    PassedPawns,
+    CandidatePawns,
+    ConnectedPawns,
+    DoubledPawns,
+    IsolatedPawns,
+    BackwardPawns,
+    Files,
+    Mobility,
+    Pieces,
+    KingSafety,
+    Tempo,
+}
+pub const NUM_EVAL_TERMS : usize = 13;
+const EVAL_TERM_NAMES : [&str; NUM_EVAL_TERMS] = ["Material", "Piece squares", "Passed pawns", "Candidate pawns",
+    "Connected pawns", "Doubled pawns", "Isolated pawns", "Backward pawns", "Files", "Mobility", "Pieces",
+    "King safety", "Tempo"];
+// Indexed by EvalTerm then colour
+type EvalTerms = [[Score; NUM_COLORS]; NUM_EVAL_TERMS];
 
 pub fn eval_is_mate(eval : i32) -> bool {
     eval < i32::MIN + 1200 || eval > -(i32::MIN+1200) 
@@ -106,20 +85,26 @@ pub fn eval_is_mate(eval : i32) -> bool {
 
 pub struct Evaluator {
     bb_utils : BitBoardUtils,
-
+    pawn_table : PawnTable,
 }
 impl Evaluator {
     pub fn new() -> Evaluator {
         Evaluator {  
             bb_utils : BitBoardUtils::new(),
+            pawn_table : PawnTable::new(),
         }
     }
 
-    // const ALL_COLORS : [Color; chess::NUM_COLORS] = [Color::White, Color::Black];
-    pub fn eval_is_mate(&self, eval : i32) -> bool {
-        eval < i32::MIN + 1200 || eval > -(i32::MIN+1200) 
+    // Lets the caller keep the pawn table, and what's in it, for the next search
+    pub fn set_pawn_table(&mut self, pawn_table : PawnTable) {
+        self.pawn_table = pawn_table;
+    }
+
+    pub fn take_pawn_table(&mut self) -> PawnTable {
+        mem::take(&mut self.pawn_table)
     }
 
+    // const ALL_COLORS : [Color; chess::NUM_COLORS] = [Color::White, Color::Black];
     pub fn eval_distance_to_mate(&self, eval : i32) -> i32 {
         if eval < i32::MIN+1200 {
             return (-eval+(i32::MIN+1000))/2;
@@ -129,55 +114,20 @@ impl Evaluator {
         0
     }
 
-    fn centralization_midgame(&self, bit_board : BitBoard, piece : Piece, _color : Color) -> i32 {
-        let mut eval = 0;
-        let mut bb = bit_board;
-        while bb.0 != 0 {
-            let loc = bb.0.trailing_zeros();
-            eval += ALL_CENTRALIZATION_MIDGAME[piece.to_index()][loc as usize];
-            let mut pos : u64 = 1;
-            pos = pos << loc;
-            bb ^= BitBoard(pos);
+    // Placement of color's pieces
+    fn side_piece_squares(&self, board : &Board, color : Color) -> Score {
+        let mut eval = Score::default();
+        for square in *board.color_combined(color) {
+            eval += pst::piece_square(board.piece_on(square).unwrap(), color, square);
         }
         eval
     }
 
-    fn position_endgame(&self, bit_board : BitBoard, piece : Piece, color : Color) -> i32 {
-        let mut eval = 0;
-        let mut bb = bit_board;
-        let endgame_arr;
-        match color {
-            Color::White => endgame_arr = ALL_WHITE_POSITION_ENDGAME,
-            Color::Black => endgame_arr = ALL_BLACK_POSITION_ENDGAME,
-        }
-
-        while bb.0 != 0 {
-            let loc = bb.0.trailing_zeros();
-            eval += endgame_arr[piece.to_index()][loc as usize];
-            let mut pos : u64 = 1;
-            pos = pos << loc;
-            bb ^= BitBoard(pos);
-        }
-        eval
-    }
-
-    pub fn centralization_eval(&self, board : &Board, total_material : i32) -> i32 {
-        let white_bb = board.color_combined(Color::White);
-        let black_bb = board.color_combined(Color::Black);
-
-        let mut eval = 0;
-        if total_material > EARLY_ENDGAME_CUTOFF {
-            for piece in ALL_PIECES {
-                let white_piece_bb = board.pieces(piece) & white_bb;
-                let black_piece_bb = board.pieces(piece) & black_bb;
-                eval += self.centralization_midgame(white_piece_bb, piece, Color::White) - self.centralization_midgame(black_piece_bb, piece, Color::Black);
-            }        
-        } else {
-            for piece in ALL_PIECES {
-                let white_piece_bb = board.pieces(piece) & white_bb;
-                let black_piece_bb = board.pieces(piece) & black_bb;
-                eval += self.position_endgame(white_piece_bb, piece, Color::White) - self.position_endgame(black_piece_bb, piece, Color::Black);
-            }
+    fn side_material(&self, board : &Board, color : Color) -> Score {
+        let mut eval = Score::default();
+        for piece in ALL_PIECES_NO_KING {
+            let piece_bb = board.pieces(piece) & board.color_combined(color);
+            eval += PIECE_VAL[piece.to_index()] * piece_bb.popcnt() as i32;
         }
         eval
     }
@@ -195,195 +145,403 @@ impl Evaluator {
         material
     }
 
-    fn get_doubled_pawn_eval(&self, board : &Board) -> i32 {
-        let white_pawns = board.color_combined(Color::White) & board.pieces(Piece::Pawn);
-        let black_pawns = board.color_combined(Color::Black) & board.pieces(Piece::Pawn);
-        let mut eval = 0;
+    fn piece_attacks(&self, piece : Piece, square : Square, occupied : BitBoard) -> BitBoard {
+        match piece {
+            Piece::Knight => get_knight_moves(square),
+            Piece::Bishop => get_bishop_moves(square, occupied),
+            Piece::Rook => get_rook_moves(square, occupied),
+            Piece::Queen => get_bishop_moves(square, occupied) | get_rook_moves(square, occupied),
+            _ => EMPTY,
+        }
+    }
 
-        for file in ALL_FILES {
-            let white_pawns_in_file = white_pawns & self.bb_utils.file_mask[file.to_index()];
-            let black_pawns_in_file: BitBoard = black_pawns & self.bb_utils.file_mask[file.to_index()];
-            
-            let white_num_pawns_in_file = white_pawns_in_file.popcnt() as i32;
-            let black_num_pawns_in_file = black_pawns_in_file.popcnt() as i32;
-            if white_num_pawns_in_file != 0 {
-                eval -= DOUBLE_PAWN_PENALTY * (white_num_pawns_in_file - 1);
-            }
-            if black_num_pawns_in_file != 0 {
-                eval += DOUBLE_PAWN_PENALTY * (black_num_pawns_in_file - 1);
+    fn side_mobility(&self, board : &Board, color : Color) -> Score {
+        let occupied = *board.combined();
+        let enemy_pawns = board.pieces(Piece::Pawn) & board.color_combined(!color);
+        // Squares an enemy pawn covers aren't really available
+        let available = !board.color_combined(color) & !self.bb_utils.pawn_attacks(enemy_pawns, !color);
+
+        let mut eval = Score::default();
+        for piece in [Piece::Knight, Piece::Bishop, Piece::Rook, Piece::Queen] {
+            for square in board.pieces(piece) & board.color_combined(color) {
+                let moves = self.piece_attacks(piece, square, occupied);
+                let mobility = (moves & available).popcnt() as i32 - MOBILITY_BASELINE[piece.to_index()];
+                eval += MOBILITY_WEIGHT[piece.to_index()] * mobility;
             }
         }
         eval
     }
 
-    fn get_isolated_pawn_eval(&self, board : &Board) -> i32 {
-        let mut eval = 0;
-        let white_pawns = board.color_combined(Color::White) & board.pieces(Piece::Pawn);
-        let black_pawns = board.color_combined(Color::Black) & board.pieces(Piece::Pawn);
-
-
-        for file in MIDDLE_FILES {
-            let white_pawns_in_file = white_pawns & self.bb_utils.file_mask[file.to_index()];
-            let black_pawns_in_file: BitBoard = black_pawns & self.bb_utils.file_mask[file.to_index()];
-            
-            
-            let white_num_pawns_in_file = white_pawns_in_file.popcnt() as i32;
-            let black_num_pawns_in_file = black_pawns_in_file.popcnt() as i32;
-            if white_num_pawns_in_file != 0 && (white_pawns & self.bb_utils.file_mask[file.left().to_index()] != EMPTY) 
-                && (white_pawns & self.bb_utils.file_mask[file.right().to_index()] != EMPTY){
-                eval -= ISOLATED_PAWN_PENALTY * white_num_pawns_in_file;
-            }
-            if black_num_pawns_in_file != 0 && (black_pawns & self.bb_utils.file_mask[file.left().to_index()] != EMPTY) 
-                && (black_pawns & self.bb_utils.file_mask[file.right().to_index()] != EMPTY){
-                eval += DOUBLE_PAWN_PENALTY * black_num_pawns_in_file;
+    fn side_piece_activity(&self, board : &Board, color : Color) -> Score {
+        let our_pieces = board.color_combined(color);
+        let our_pawns = board.pieces(Piece::Pawn) & our_pieces;
+        let enemy_pawns = board.pieces(Piece::Pawn) & board.color_combined(!color);
+        let defended_by_pawn = self.bb_utils.pawn_attacks(our_pawns, color);
+
+        let mut eval = Score::default();
+        for (piece, bonus) in [(Piece::Knight, KNIGHT_OUTPOST_BONUS), (Piece::Bishop, BISHOP_OUTPOST_BONUS)] {
+            for square in board.pieces(piece) & our_pieces & defended_by_pawn {
+                let rank = square.get_rank().to_index();
+                if !OUTPOST_RANKS.contains(&relative_rank(color, rank)) {
+                    continue;
+                }
+                let challengers = get_adjacent_files(square.get_file()) & self.bb_utils.ranks_in_front(color, rank) & enemy_pawns;
+                if challengers == EMPTY {
+                    eval += bonus;
+                }
             }
         }
 
-        // TODO a pawn and h pawn can be isolated too
+        // The seventh is only worth it if there are pawns to eat there or the king is stuck behind it
+        let seventh_rank = self.bb_utils.rank_mask[relative_rank(color, SEVENTH_RANK)];
+        let eighth_rank = self.bb_utils.rank_mask[relative_rank(color, SEVENTH_RANK + 1)];
+        let enemy_king = board.pieces(Piece::King) & board.color_combined(!color);
+        if (enemy_pawns & seventh_rank) != EMPTY || (enemy_king & eighth_rank) != EMPTY {
+            let rooks_on_seventh = board.pieces(Piece::Rook) & our_pieces & seventh_rank;
+            eval += ROOK_ON_SEVENTH_BONUS * rooks_on_seventh.popcnt() as i32;
+        }
 
+        if (board.pieces(Piece::Bishop) & our_pieces).popcnt() >= 2 {
+            eval += BISHOP_PAIR_BONUS;
+        }
         eval
     }
 
-    fn get_passed_pawn_eval(&self, board : &Board) -> i32 {
-        let mut eval = 0;
-        let white_pawns = board.color_combined(Color::White) & board.pieces(Piece::Pawn);
-        let black_pawns = board.color_combined(Color::Black) & board.pieces(Piece::Pawn);
-
-
-        for file in MIDDLE_FILES {
-            let white_pawns_in_file = white_pawns & self.bb_utils.file_mask[file.to_index()];
-            let black_pawns_in_file: BitBoard = black_pawns & self.bb_utils.file_mask[file.to_index()];
-            
-            
-            let white_num_pawns_in_file = white_pawns_in_file.popcnt() as i32;
-            let black_num_pawns_in_file = black_pawns_in_file.popcnt() as i32;
-            if white_num_pawns_in_file != 0 && (black_pawns & self.bb_utils.file_mask[file.left().to_index()] != EMPTY) 
-                && (black_pawns & self.bb_utils.file_mask[file.right().to_index()] != EMPTY) && black_num_pawns_in_file == 0{
-                eval += PASSED_PAWN_BONUS;
+    // The king zone is the king's square, the squares around it, and one more rank towards the enemy
+    fn king_zone(&self, king_square : Square, color : Color) -> BitBoard {
+        let around = get_king_moves(king_square) | BitBoard::from_square(king_square);
+        match color {
+            Color::White => around | BitBoard(around.0 << 8),
+            Color::Black => around | BitBoard(around.0 >> 8),
+        }
+    }
+
+    // How safe color's king is, as a score for color
+    fn side_king_safety(&self, board : &Board, color : Color) -> Score {
+        let king_square = board.king_square(color);
+        let king_rank = king_square.get_rank().to_index();
+        let our_pawns = board.pieces(Piece::Pawn) & board.color_combined(color);
+        let enemy_pawns = board.pieces(Piece::Pawn) & board.color_combined(!color);
+        let mut eval = Score::default();
+
+        // Attack units from every enemy piece hitting the king zone
+        let zone = self.king_zone(king_square, color);
+        let occupied = *board.combined();
+        let mut attackers = 0;
+        let mut attack_units = 0;
+        for piece in [Piece::Knight, Piece::Bishop, Piece::Rook, Piece::Queen] {
+            for square in board.pieces(piece) & board.color_combined(!color) {
+                let attacked = self.piece_attacks(piece, square, occupied) & zone;
+                if attacked != EMPTY {
+                    attackers += 1;
+                    attack_units += KING_ATTACK_WEIGHT[piece.to_index()] * attacked.popcnt() as i32;
+                }
             }
-            if black_num_pawns_in_file != 0 && (white_pawns & self.bb_utils.file_mask[file.left().to_index()] != EMPTY) 
-                && (white_pawns & self.bb_utils.file_mask[file.right().to_index()] != EMPTY) && white_num_pawns_in_file == 0{
-                eval -= PASSED_PAWN_BONUS;
+        }
+        let danger = i32::min(attack_units * attack_units / KING_DANGER_DIVISOR, MAX_KING_DANGER);
+        eval.mg -= danger * KING_ATTACKERS_SCALE[usize::min(attackers, KING_ATTACKERS_SCALE.len() - 1)] / 100;
+
+        let king_file = king_square.get_file();
+        let files_around_king = get_adjacent_files(king_file) | self.bb_utils.file_mask(king_file);
+        let forward = if color == Color::White { 1 } else { -1 };
+        for (distance, bonus) in KING_SHIELD_BONUS.iter().enumerate() {
+            let shield_rank = king_rank as i32 + forward * (distance as i32 + 1);
+            if (0..8).contains(&shield_rank) {
+                let shield = our_pawns & files_around_king & self.bb_utils.rank_mask[shield_rank as usize];
+                eval += *bonus * shield.popcnt() as i32;
             }
         }
 
-        // TODO a pawn and h pawn can be passed too
+        for file in ALL_FILES {
+            let file_mask = self.bb_utils.file_mask(file);
+            if (file_mask & files_around_king) == EMPTY {
+                continue;
+            }
+            if (our_pawns & file_mask) == EMPTY {
+                if (enemy_pawns & file_mask) == EMPTY {
+                    eval -= KING_OPEN_FILE_PENALTY;
+                } else {
+                    eval -= KING_SEMI_OPEN_FILE_PENALTY;
+                }
+            }
+        }
 
+        // Enemy pawns marching up the files around the king
+        let stormers = enemy_pawns & files_around_king & self.bb_utils.ranks_in_front(color, king_rank);
+        for square in stormers {
+            let distance = (square.get_rank().to_index() as i32 - king_rank as i32).unsigned_abs() as usize;
+            if distance < PAWN_STORM_PENALTY.len() {
+                eval -= PAWN_STORM_PENALTY[distance];
+            }
+        }
         eval
     }
 
+    // Every square color attacks
+    fn side_attacks(&self, board : &Board, color : Color) -> BitBoard {
+        let occupied = *board.combined();
+        let our_pieces = board.color_combined(color);
+        let mut attacks = self.bb_utils.pawn_attacks(board.pieces(Piece::Pawn) & our_pieces, color);
+        attacks |= get_king_moves(board.king_square(color));
+        for piece in [Piece::Knight, Piece::Bishop, Piece::Rook, Piece::Queen] {
+            for square in board.pieces(piece) & our_pieces {
+                attacks |= self.piece_attacks(piece, square, occupied);
+            }
+        }
+        attacks
+    }
 
-    fn get_king_safety(&self, board : &Board) -> i32 {
-        let mut eval = 0;
-        let white_bb = board.color_combined(Color::White);
-        let black_bb = board.color_combined(Color::Black);
-        let white_kings = white_bb & board.pieces(Piece::King);
-        let black_kings = black_bb & board.pieces(Piece::King);
-        let white_pawns = white_bb & board.pieces(Piece::Pawn);
-        let black_pawns = black_bb & board.pieces(Piece::Pawn);
-
-        let white_protectors = white_pawns & (BitBoard(white_kings.0 << 7) | BitBoard(white_kings.0 << 8) | BitBoard(white_kings.0 << 9));
-        let black_protectors = black_pawns & (BitBoard(black_kings.0 >> 7) | BitBoard(black_kings.0 >> 8) | BitBoard(black_kings.0 >> 9));
-        let white_second_protectors = white_pawns & (BitBoard(white_kings.0 << 15) | BitBoard(white_kings.0 << 16) | BitBoard(white_kings.0 << 9));
-        let black_second_protectors = black_pawns & (BitBoard(black_kings.0 >> 15) | BitBoard(black_kings.0 >> 16) | BitBoard(black_kings.0 >> 9));
-        eval += PAWN_PROTECTOR_BONUS * white_protectors.popcnt() as i32;
-        eval -= PAWN_PROTECTOR_BONUS * black_protectors.popcnt() as i32;
-        eval += PAWN_SECOND_PROTECTOR_BONUS * white_second_protectors.popcnt() as i32;
-        eval -= PAWN_SECOND_PROTECTOR_BONUS * black_second_protectors.popcnt() as i32;
-
-        for file in ALL_FILES {
-            if (white_kings & self.bb_utils.file_mask(file)) != EMPTY {
-                if (white_pawns & self.bb_utils.file_mask(file)) != EMPTY {
-                    eval += KING_ON_SEMI_OPEN;
+    // How likely color's passed pawns are to promote, beyond the pawn structure bonus they already have
+    fn side_passed_pawns(&self, board : &Board, color : Color, passed : BitBoard) -> Score {
+        let passed = passed & board.color_combined(color);
+        if passed == EMPTY {
+            return Score::default()
+        }
+        let occupied = *board.combined();
+        let our_king = board.king_square(color);
+        let enemy_king = board.king_square(!color);
+        let enemy_attacks = self.side_attacks(board, !color);
+        let enemy_pieces = board.color_combined(!color) & !board.pieces(Piece::Pawn) & !board.pieces(Piece::King);
+        let promotion_rank = relative_rank(color, 7);
+        let mut eval = Score::default();
+
+        for square in passed {
+            let rank = square.get_rank().to_index();
+            let relative = relative_rank(color, rank) as i32;
+            let weight = i32::max(relative - PASSER_MIN_RANK, 0);
+            let path = self.bb_utils.file_mask(square.get_file()) & self.bb_utils.ranks_in_front(color, rank);
+            let stop_square = square.uforward(color);
+            let path_is_free = (path & occupied) == EMPTY;
+
+            // The enemy king wants to be in front of the pawn, our king wants to escort it
+            eval.eg += weight * (PASSER_ENEMY_KING_DISTANCE * square_distance(enemy_king, stop_square)
+                - PASSER_OWN_KING_DISTANCE * square_distance(our_king, stop_square));
+
+            if path_is_free {
+                if (path & enemy_attacks) == EMPTY {
+                    eval += PASSER_UNCONTESTED_PATH_BONUS * weight;
+                } else {
+                    eval += PASSER_FREE_PATH_BONUS * weight;
                 }
+            } else if (BitBoard::from_square(stop_square) & occupied) != EMPTY {
+                eval -= PASSER_BLOCKED_PENALTY * weight;
             }
-            if (black_kings & self.bb_utils.file_mask(file)) != EMPTY {
-                if (black_pawns & self.bb_utils.file_mask(file)) != EMPTY {
-                    eval -= KING_ON_SEMI_OPEN;
+
+            // Rule of the square. With only a king to stop it, the pawn promotes if the king is too far away
+            if enemy_pieces == EMPTY && path_is_free {
+                let promotion_square = Square::make_square(Rank::from_index(promotion_rank), square.get_file());
+                // From the second rank the first move can be a double push
+                let moves_to_promote = i32::min(7 - relative, 5);
+                let mut king_moves = square_distance(enemy_king, promotion_square);
+                if board.side_to_move() != color {
+                    king_moves -= 1;
+                }
+                if king_moves > moves_to_promote {
+                    eval.eg += UNSTOPPABLE_PAWN_BONUS - UNSTOPPABLE_PAWN_PER_MOVE * moves_to_promote;
                 }
             }
         }
         eval
     }
 
-    fn get_files_eval(&self, board : &Board) -> i32 {
-        let white_bb = board.color_combined(Color::White);
-        let black_bb = board.color_combined(Color::Black);
-        let white_rooks = white_bb & board.pieces(Piece::Rook);
-        let black_rooks = black_bb & board.pieces(Piece::Rook);
-        let white_queens = white_bb & board.pieces(Piece::Queen);
-        let black_queens = black_bb & board.pieces(Piece::Queen);
-        let white_pawns = white_bb & board.pieces(Piece::Pawn);
-        let black_pawns = black_bb & board.pieces(Piece::Pawn);
-
-        let mut eval = 0;
+    // Rooks and queens on files without any of color's pawns
+    fn side_files(&self, board : &Board, color : Color) -> Score {
+        let our_pawns = board.pieces(Piece::Pawn) & board.color_combined(color);
+        let enemy_pawns = board.pieces(Piece::Pawn) & board.color_combined(!color);
+        let heavy_pieces = (board.pieces(Piece::Rook) | board.pieces(Piece::Queen)) & board.color_combined(color);
 
+        let mut eval = Score::default();
         for file in ALL_FILES {
-            if ((white_queens | white_rooks) & self.bb_utils.file_mask(file)) != EMPTY {
-                if white_pawns & self.bb_utils.file_mask(file) == EMPTY {
-                    if black_pawns & self.bb_utils.file_mask(file) == EMPTY {
-                        eval += OPEN_FILE_BONUS;
-                    } else{
-                        eval += SEMI_OPEN_FILE_BONUS;
-                    }
-                }
-            }
-            if ((black_queens | black_rooks) & self.bb_utils.file_mask(file)) != EMPTY {
-                if black_pawns & self.bb_utils.file_mask(file) == EMPTY {
-                    if white_pawns & self.bb_utils.file_mask(file) == EMPTY {
-                        eval -= OPEN_FILE_BONUS;
-                    } else{
-                        eval -= SEMI_OPEN_FILE_BONUS;
-                    }
+            let file_mask = self.bb_utils.file_mask(file);
+            if (heavy_pieces & file_mask) != EMPTY && (our_pawns & file_mask) == EMPTY {
+                if (enemy_pawns & file_mask) == EMPTY {
+                    eval += OPEN_FILE_BONUS;
+                } else {
+                    eval += SEMI_OPEN_FILE_BONUS;
                 }
             }
         }
         eval
     }
 
-    pub fn eval(&self, board : &Board, ply : u32) -> i32 {
-        let mut eval : i32 = 0;
+    // Every term for both sides, each from its own side's point of view
+    fn eval_terms(&mut self, board : &Board) -> EvalTerms {
+        let pawn_entry = self.pawn_table.probe(board, &self.bb_utils);
+        let mut terms = [[Score::default(); NUM_COLORS]; NUM_EVAL_TERMS];
+        for color in [Color::White, Color::Black] {
+            let side = color.to_index();
+            let pawns = pawn_entry.terms[side];
+            terms[EvalTerm::Material as usize][side] = self.side_material(board, color);
+            terms[EvalTerm::PieceSquares as usize][side] = self.side_piece_squares(board, color);
+            terms[EvalTerm::PassedPawns as usize][side] = pawns.passed + self.side_passed_pawns(board, color, pawn_entry.passed);
+            terms[EvalTerm::CandidatePawns as usize][side] = pawns.candidate;
+            terms[EvalTerm::ConnectedPawns as usize][side] = pawns.connected;
+            terms[EvalTerm::DoubledPawns as usize][side] = pawns.doubled;
+            terms[EvalTerm::IsolatedPawns as usize][side] = pawns.isolated;
+            terms[EvalTerm::BackwardPawns as usize][side] = pawns.backward;
+            terms[EvalTerm::Files as usize][side] = self.side_files(board, color);
+            terms[EvalTerm::Mobility as usize][side] = self.side_mobility(board, color);
+            terms[EvalTerm::Pieces as usize][side] = self.side_piece_activity(board, color);
+            terms[EvalTerm::KingSafety as usize][side] = self.side_king_safety(board, color);
+        }
+        terms[EvalTerm::Tempo as usize][board.side_to_move().to_index()] = TEMPO_VALUE;
+        terms
+    }
+
+    // Adds the terms up from white's point of view. Returns the tapered score and the scale factor used on it
+    fn score_terms(&self, board : &Board, terms : &EvalTerms) -> (i32, i32) {
+        let mut score = Score::default();
+        for term in terms {
+            score += term[Color::White.to_index()] - term[Color::Black.to_index()];
+        }
+        // Drawish material takes away most of the advantage of whoever is ahead
+        let strong = if score.eg >= 0 { Color::White } else { Color::Black };
+        let scale = endgame::scale_factor(board, strong);
+        score.eg = score.eg * scale / endgame::SCALE_NORMAL;
+        (score.taper(score::game_phase(board)), scale)
+    }
 
+    pub fn eval(&mut self, board : &Board, ply : u32) -> i32 {
         // TODO fixup
         match board.status() {
             BoardStatus::Checkmate => return i32::MIN + 1000 + ply as i32,
             BoardStatus::Stalemate => return 0, 
             BoardStatus::Ongoing => (),
         }
+        if let Some(value) = endgame::evaluate(board) {
+            return if board.side_to_move() == Color::White { value } else { -value }
+        }
 
+        let terms = self.eval_terms(board);
+        let (score, _) = self.score_terms(board, &terms);
+        if board.side_to_move() == Color::White { score } else { -score }
+    }
 
-        let white_bb = board.color_combined(Color::White);
-        let black_bb = board.color_combined(Color::Black);
+    // The eval as a table of every term for each side, for the eval command. It adds up the same terms eval
+    // does, and ends with what eval itself returns
+    pub fn trace(&mut self, board : &Board) -> String {
+        let terms = self.eval_terms(board);
+        let (score, scale) = self.score_terms(board, &terms);
+        let mut table = String::new();
+        table += "           Term |     White     |     Black     |     Total\n";
+        table += "                |    MG     EG  |    MG     EG  |    MG     EG\n";
+        table += "----------------+---------------+---------------+--------------\n";
+        let mut totals = [Score::default(); NUM_COLORS];
+        for (name, term) in EVAL_TERM_NAMES.iter().zip(terms.iter()) {
+            table += &trace_row(name, term);
+            totals[Color::White.to_index()] += term[Color::White.to_index()];
+            totals[Color::Black.to_index()] += term[Color::Black.to_index()];
+        }
+        table += "----------------+---------------+---------------+--------------\n";
+        table += &trace_row("Total", &totals);
+        table += &format!("\nPhase {}/{}, endgame scale factor {}/{}\n", score::game_phase(board), score::MAX_PHASE,
+            scale, endgame::SCALE_NORMAL);
+        table += &format!("Tapered eval {score} (white side)\n");
 
-        // let pawns = [white_bb & board.pieces(Piece::Pawn), black_bb & board.pieces(Piece::Pawn)];
-        // let rooks = [white_bb & board.pieces(Piece::Rook), black_bb & board.pieces(Piece::Rook)];
-        
-        let mut total_material = 0;
-        // Do material eval
-        for piece in ALL_PIECES_NO_KING {
-            let white_piece_bb = board.pieces(piece) & white_bb;
-            let black_piece_bb = board.pieces(piece) & black_bb;
-            eval += NAIVE_PIECE_VAL[piece.to_index()] * (white_piece_bb.popcnt() as i32 - black_piece_bb.popcnt() as i32);
-            total_material += NAIVE_PIECE_VAL[piece.to_index()] * (white_piece_bb.popcnt() as i32 + black_piece_bb.popcnt() as i32);
+        match board.status() {
+            BoardStatus::Checkmate => table += "Checkmate\n",
+            BoardStatus::Stalemate => table += "Stalemate\n",
+            BoardStatus::Ongoing => if endgame::evaluate(board).is_some() {
+                table += "Known endgame, the terms above are replaced by its own evaluation\n";
+            },
         }
+        let eval = self.eval(board, 0);
+        let white_eval = if board.side_to_move() == Color::White { eval } else { -eval };
+        table += &format!("Final evaluation {white_eval} (white side)\n");
+        table
+    }
+}
 
-        eval += self.centralization_eval(board, total_material);
-        eval += self.get_doubled_pawn_eval(board);
-        eval += self.get_isolated_pawn_eval(board);
-        eval += self.get_passed_pawn_eval(board);
-        eval += self.get_files_eval(board);
+fn trace_row(name : &str, term : &[Score; NUM_COLORS]) -> String {
+    let white = term[Color::White.to_index()];
+    let black = term[Color::Black.to_index()];
+    let total = white - black;
+    format!("{name:>15} | {:>5}  {:>5} | {:>5}  {:>5} | {:>5}  {:>5}\n", white.mg, white.eg, black.mg, black.eg, total.mg, total.eg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    // Positions that should score the same for the side to move once the board is flipped and the colours swapped
+    const SYMMETRY_FENS : [&str; 17] = [
+        // Every stage of the game, so the taper is exercised from full midgame to bare endgame
+        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+        "r1bqkb1r/pppp1ppp/2n2n2/4p3/2B1P3/5N2/PPPP1PPP/RNBQK2R w KQkq - 4 4",
+        "2r3k1/pp3ppp/4p3/3pP3/3P4/P4N2/1P3PPP/2R3K1 b - - 0 25",
+        "8/5pk1/6p1/8/3K4/6P1/5P2/8 w - - 0 40",
+        // Pieces on good and bad squares, kings away from home
+        "r1b2rk1/pp1n1ppp/2p1pn2/q7/1bPP4/2N1PN2/PPQB1PPP/R3KB1R w KQ - 0 9",
+        "4rrk1/1pq2pbp/p2p1np1/2pP4/P1P1n3/2N2N1P/1P2BPP1/R2QR1K1 b - - 0 16",
+        "8/2k5/1pn5/p1p1K3/P1P2N2/1P6/8/8 w - - 0 50",
+        // Mobility, an outpost knight, a rook on the seventh and a bishop pair against bishop and knight
+        "2r3k1/pR3ppp/4p3/3pPN2/3P4/P7/5PPP/6K1 b - - 0 25",
+        "r4rk1/1b3ppp/p3p3/1p1nP3/3N4/1B6/PP3PPP/2RR2K1 w - - 0 20",
+        "2kr3r/ppp2ppp/2n5/8/2B1b3/2B5/PPP2PPP/R4RK1 w - - 0 14",
+        // Attacks on the king, a broken shield, an open file and a pawn storm
+        "r1bq1rk1/pp2bpp1/2n1p2p/3pP2Q/3P4/2PB1N2/P4PPP/R1B1K2R w KQ - 0 12",
+        "2kr3r/ppp2p2/2n1bq2/4p1pp/4P3/2PP1N1P/PP1NBPP1/R2Q1RK1 b - - 0 14",
+        "r4rk1/pp3p1p/2n3pQ/3q4/8/2P5/P4PPP/R4RK1 w - - 0 20",
+        // Passed pawn races and a pawn the enemy king can't catch
+        "8/5k2/8/1P6/8/6p1/4K3/8 w - - 0 45",
+        "8/p4k2/8/8/5P2/8/1K6/8 b - - 0 50",
+        "6k1/5ppp/8/P7/8/8/5PPP/6K1 w - - 0 30",
+        "8/8/3k4/8/1pP5/8/5K2/8 w - - 0 55",
+    ];
+
+    // Flips the board top to bottom and swaps the colours. En passant squares are dropped
+    fn mirror_fen(fen : &str) -> String {
+        let swap_case = |c : char| if c.is_ascii_uppercase() { c.to_ascii_lowercase() } else { c.to_ascii_uppercase() };
+        let fields : Vec<&str> = fen.split_whitespace().collect();
+        let ranks : Vec<String> = fields[0].split('/').rev().map(|rank| rank.chars().map(swap_case).collect()).collect();
+        let side = if fields[1] == "w" { "b" } else { "w" };
+        let mut castling : Vec<char> = fields[2].chars().map(swap_case).collect();
+        castling.sort();
+        let castling : String = castling.into_iter().collect();
+        format!("{} {side} {} - {} {}", ranks.join("/"), if castling == "-" { "-" } else { &castling }, fields[4], fields[5])
+    }
 
-        if total_material > EARLY_ENDGAME_CUTOFF {
-            eval += self.get_king_safety(board);
+    #[test]
+    fn mirror_symmetry() {
+        let mut evaluator = Evaluator::new();
+        for fen in SYMMETRY_FENS {
+            let board = Board::from_str(fen).unwrap();
+            let mirrored = Board::from_str(&mirror_fen(fen)).unwrap();
+            assert_eq!(evaluator.eval(&board, 0), evaluator.eval(&mirrored, 0), "{fen}");
         }
-        
+    }
 
-        eval += TEMPO_VALUE;
+    // The number in a trace line like "Final evaluation 35 (white side)"
+    fn trace_value(trace : &str, prefix : &str) -> Option<i32> {
+        let line = trace.lines().find(|line| line.starts_with(prefix))?;
+        line[prefix.len()..].split_whitespace().next()?.parse().ok()
+    }
 
-        match board.side_to_move() {
-            Color::Black => eval = eval * -1,
-            Color::White => (),
+    #[test]
+    fn trace_matches_eval() {
+        let fens = [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r1bqkb1r/pppp1ppp/2n2n2/4p3/2B1P3/5N2/PPPP1PPP/RNBQK2R b KQkq - 4 4",
+            "2r3k1/pp3ppp/4p3/3pP3/3P4/P4N2/1P3PPP/2R3K1 b - - 0 25",
+            // Opposite bishops, scaled down
+            "4k3/5p2/8/2b5/8/3B4/4PP2/4K3 w - - 0 1",
+            // Known endgames, the trace's terms don't decide these
+            "4k3/8/8/8/8/8/8/3QK3 b - - 0 1",
+            "8/8/8/8/8/2k5/2p5/R3K3 w - - 0 1",
+        ];
+        let mut evaluator = Evaluator::new();
+        for fen in fens {
+            let board = Board::from_str(fen).unwrap();
+            let trace = evaluator.trace(&board);
+            let eval = evaluator.eval(&board, 0);
+            let white_eval = if board.side_to_move() == Color::White { eval } else { -eval };
+            assert_eq!(trace_value(&trace, "Final evaluation "), Some(white_eval), "{fen}");
+            if endgame::evaluate(&board).is_none() {
+                assert_eq!(trace_value(&trace, "Tapered eval "), Some(white_eval), "{fen}");
+            } else {
+                assert!(trace.contains("Known endgame"), "{fen}");
+            }
         }
-        eval
     }
-}
\ No newline at end of file
+}
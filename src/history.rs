@@ -0,0 +1,161 @@
+// Positions played so far, in the game and along the current search path
+use chess::{Board, BoardStatus, ChessMove, Piece};
+
+const FIFTY_MOVE_HALFMOVES : u32 = 100;
+
+#[derive(Clone, Copy)]
+struct HistoryEntry {
+    hash : u64,
+    // Halfmoves since the last capture or pawn move
+    halfmove_clock : u32,
+    // Halfmoves since the last null move. Positions before a null move can't be repeated through it
+    plies_from_null : u32,
+}
+
+#[derive(Clone)]
+pub struct PositionHistory {
+    entrys : Vec<HistoryEntry>,
+}
+
+impl PositionHistory {
+    pub fn new(board : &Board, halfmove_clock : u32) -> PositionHistory {
+        PositionHistory {
+            entrys : vec![HistoryEntry {
+                hash : board.get_hash(),
+                halfmove_clock : halfmove_clock,
+                plies_from_null : 0,
+            }],
+        }
+    }
+
+    // The halfmove clock is the fifth field of a fen, Board::from_str throws it away
+    pub fn halfmove_clock_from_fen(fen : &str) -> u32 {
+        fen.split_whitespace().nth(4).and_then(|clock| clock.parse().ok()).unwrap_or(0)
+    }
+
+    fn last(&self) -> HistoryEntry {
+        self.entrys[self.entrys.len() - 1]
+    }
+
+    // Record chess_move being played from board
+    pub fn push_move(&mut self, board : &Board, chess_move : ChessMove) {
+        let last = self.last();
+        let is_capture = board.piece_on(chess_move.get_dest()).is_some();
+        let is_pawn_move = board.piece_on(chess_move.get_source()) == Some(Piece::Pawn);
+        let halfmove_clock = if is_capture || is_pawn_move { 0 } else { last.halfmove_clock + 1 };
+
+        self.entrys.push(HistoryEntry {
+            hash : board.make_move_new(chess_move).get_hash(),
+            halfmove_clock : halfmove_clock,
+            plies_from_null : last.plies_from_null + 1,
+        });
+    }
+
+    pub fn push_null_move(&mut self, board : &Board) {
+        let last = self.last();
+        self.entrys.push(HistoryEntry {
+            hash : board.null_move().unwrap().get_hash(),
+            halfmove_clock : last.halfmove_clock + 1,
+            plies_from_null : 0,
+        });
+    }
+
+    pub fn ends_with(&self, board : &Board) -> bool {
+        self.last().hash == board.get_hash()
+    }
+
+    pub fn pop(&mut self) {
+        self.entrys.pop();
+    }
+
+    // Any earlier occurence of the current position counts. If it can be repeated once it can be repeated twice
+    fn is_repetition(&self) -> bool {
+        let last = self.last();
+        let newest = self.entrys.len() - 1;
+        let lookback = usize::min(u32::min(last.halfmove_clock, last.plies_from_null) as usize, newest);
+
+        // Only positions with the same side to move can match, so step back two plies at a time
+        let mut distance = 4;
+        while distance <= lookback {
+            if self.entrys[newest - distance].hash == last.hash {
+                return true
+            }
+            distance += 2;
+        }
+        false
+    }
+
+    // board should be the position at the top of the history
+    pub fn is_draw(&self, board : &Board) -> bool {
+        if self.last().halfmove_clock >= FIFTY_MOVE_HALFMOVES && board.status() != BoardStatus::Checkmate {
+            return true
+        }
+        self.is_repetition()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chess::Square;
+    use std::str::FromStr;
+
+    fn play(history : &mut PositionHistory, board : &mut Board, moves : &[(Square, Square)]) {
+        for (source, dest) in moves {
+            let chess_move = ChessMove::new(*source, *dest, None);
+            history.push_move(board, chess_move);
+            *board = board.make_move_new(chess_move);
+        }
+    }
+
+    #[test]
+    fn knight_shuffle_repeats() {
+        let mut board = Board::default();
+        let mut history = PositionHistory::new(&board, 0);
+        play(&mut history, &mut board, &[(Square::G1, Square::F3), (Square::G8, Square::F6), (Square::F3, Square::G1)]);
+        assert!(!history.is_repetition());
+        play(&mut history, &mut board, &[(Square::F6, Square::G8)]);
+        assert!(history.is_repetition());
+        assert!(history.is_draw(&board));
+        // Still a repetition two plies later, now of the position after Nf3
+        play(&mut history, &mut board, &[(Square::G1, Square::F3)]);
+        assert!(history.is_repetition());
+    }
+
+    #[test]
+    fn lookback_stops_at_null_move() {
+        let mut board = Board::default();
+        let mut history = PositionHistory::new(&board, 0);
+        history.push_null_move(&board);
+        board = board.null_move().unwrap();
+        play(&mut history, &mut board, &[(Square::G8, Square::F6)]);
+        history.push_null_move(&board);
+        board = board.null_move().unwrap();
+        play(&mut history, &mut board, &[(Square::F6, Square::G8)]);
+        // Same position as the start four plies back, but only through null moves
+        assert_eq!(history.entrys[0].hash, history.last().hash);
+        assert!(!history.is_repetition());
+    }
+
+    #[test]
+    fn fifty_move_draw() {
+        let mut board = Board::from_str("8/8/8/8/8/5k2/8/Q3K3 w - - 99 80").unwrap();
+        let mut history = PositionHistory::new(&board, 99);
+        assert!(!history.is_draw(&board));
+        play(&mut history, &mut board, &[(Square::E1, Square::E2)]);
+        assert!(history.is_draw(&board));
+        // A pawn move resets the clock
+        let board = Board::from_str("4k3/8/8/8/8/8/4P3/4K3 w - - 99 80").unwrap();
+        let mut history = PositionHistory::new(&board, 99);
+        history.push_move(&board, ChessMove::new(Square::E2, Square::E4, None));
+        assert!(!history.is_draw(&board.make_move_new(ChessMove::new(Square::E2, Square::E4, None))));
+    }
+
+    #[test]
+    fn fifty_move_draw_not_over_mate() {
+        let board = Board::from_str("R5k1/5ppp/8/8/8/8/8/6K1 b - - 100 80").unwrap();
+        assert_eq!(board.status(), BoardStatus::Checkmate);
+        let history = PositionHistory::new(&board, 100);
+        assert!(!history.is_draw(&board));
+    }
+}
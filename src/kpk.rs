@@ -0,0 +1,160 @@
+// King and pawn against king bitbase
+// Positions are stored with the pawn's side as white and the pawn on files a to d, anything else is mirrored
+// into that. The whole table is solved the first time it's probed, working back from the positions where the
+// pawn promotes safely or is lost.
+use chess::{BitBoard, Color, Square, ALL_SQUARES, EMPTY, get_king_moves, get_pawn_attacks};
+use std::sync::OnceLock;
+use crate::bb_utils::square_distance;
+
+// Side to move, both kings, and the pawn on one of 24 squares: files a to d, ranks 2 to 7
+const KPK_SIZE : usize = 2 * 24 * 64 * 64;
+// Results are bit flags so the results of every move can be or'ed together
+const INVALID : u8 = 0;
+const UNKNOWN : u8 = 1;
+const DRAW : u8 = 2;
+const WIN : u8 = 4;
+
+// One bit per position, set when white wins
+static KPK_BITBASE : OnceLock<Vec<u64>> = OnceLock::new();
+
+#[derive(Clone, Copy)]
+struct KpkPosition {
+    white_to_move : bool,
+    white_king : Square,
+    black_king : Square,
+    pawn : Square,
+}
+
+fn index(white_to_move : bool, white_king : Square, black_king : Square, pawn : Square) -> usize {
+    let pawn_index = (pawn.get_rank().to_index() - 1) * 4 + pawn.get_file().to_index();
+    white_king.to_index() | black_king.to_index() << 6 | (white_to_move as usize) << 12 | pawn_index << 13
+}
+
+fn decode(index : usize) -> KpkPosition {
+    let pawn_index = index >> 13;
+    KpkPosition {
+        white_to_move : (index >> 12) & 1 == 1,
+        white_king : ALL_SQUARES[index & 63],
+        black_king : ALL_SQUARES[(index >> 6) & 63],
+        pawn : ALL_SQUARES[(pawn_index / 4 + 1) * 8 + pawn_index % 4],
+    }
+}
+
+fn pawn_attacks(pawn : Square) -> BitBoard {
+    get_pawn_attacks(pawn, Color::White, !EMPTY)
+}
+
+// What can be decided without looking at any moves
+fn initial_result(position : &KpkPosition) -> u8 {
+    let KpkPosition { white_to_move, white_king, black_king, pawn } = *position;
+    if square_distance(white_king, black_king) <= 1 || white_king == pawn || black_king == pawn
+        || (white_to_move && (pawn_attacks(pawn) & BitBoard::from_square(black_king)) != EMPTY) {
+        return INVALID
+    }
+
+    // Promotes, and the new queen can't be taken
+    if white_to_move && pawn.get_rank().to_index() == 6 {
+        let promotion_square = pawn.uforward(Color::White);
+        if white_king != promotion_square && black_king != promotion_square
+            && (square_distance(black_king, promotion_square) > 1 || square_distance(white_king, promotion_square) == 1) {
+            return WIN
+        }
+    }
+
+    if !white_to_move {
+        let covered = get_king_moves(white_king) | pawn_attacks(pawn);
+        // Stalemate, or the pawn is lost
+        if (get_king_moves(black_king) & !covered) == EMPTY
+            || (square_distance(black_king, pawn) == 1 && square_distance(white_king, pawn) > 1) {
+            return DRAW
+        }
+    }
+    UNKNOWN
+}
+
+// From the results of every move. Moves into invalid positions are illegal and add nothing
+fn classify(results : &[u8], position : &KpkPosition) -> u8 {
+    let KpkPosition { white_to_move, white_king, black_king, pawn } = *position;
+    let mut outcomes = INVALID;
+    if white_to_move {
+        for to in get_king_moves(white_king) {
+            outcomes |= results[index(false, to, black_king, pawn)];
+        }
+        // Pushes to the eighth rank were handled in initial_result
+        if pawn.get_rank().to_index() < 6 {
+            let push = pawn.uforward(Color::White);
+            if push != white_king && push != black_king {
+                outcomes |= results[index(false, white_king, black_king, push)];
+                let double_push = push.uforward(Color::White);
+                if pawn.get_rank().to_index() == 1 && double_push != white_king && double_push != black_king {
+                    outcomes |= results[index(false, white_king, black_king, double_push)];
+                }
+            }
+        }
+        if outcomes & WIN != 0 { WIN } else if outcomes & UNKNOWN != 0 { UNKNOWN } else { DRAW }
+    } else {
+        for to in get_king_moves(black_king) {
+            outcomes |= results[index(true, white_king, to, pawn)];
+        }
+        if outcomes & DRAW != 0 { DRAW } else if outcomes & UNKNOWN != 0 { UNKNOWN } else { WIN }
+    }
+}
+
+fn build_bitbase() -> Vec<u64> {
+    let positions : Vec<KpkPosition> = (0..KPK_SIZE).map(decode).collect();
+    let mut results : Vec<u8> = positions.iter().map(initial_result).collect();
+
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for i in 0..KPK_SIZE {
+            if results[i] == UNKNOWN {
+                let result = classify(&results, &positions[i]);
+                if result != UNKNOWN {
+                    results[i] = result;
+                    changed = true;
+                }
+            }
+        }
+    }
+
+    // Anything still unknown is a position white can't force a win from
+    let mut bitbase = vec![0; KPK_SIZE / 64];
+    for (i, result) in results.iter().enumerate() {
+        if *result == WIN {
+            bitbase[i / 64] |= 1 << (i % 64);
+        }
+    }
+    bitbase
+}
+
+// Whether the side with the pawn wins. Squares are from the pawn's side's point of view, moving up the board
+pub fn probe(white_to_move : bool, white_king : Square, pawn : Square, black_king : Square) -> bool {
+    // Flips the file so the pawn is on the queen side
+    let mirror = |square : Square| if pawn.get_file().to_index() >= 4 { ALL_SQUARES[square.to_index() ^ 7] } else { square };
+    let index = index(white_to_move, mirror(white_king), mirror(black_king), mirror(pawn));
+    let bitbase = KPK_BITBASE.get_or_init(build_bitbase);
+    (bitbase[index / 64] >> (index % 64)) & 1 == 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kpk_results() {
+        // King on the sixth in front of the pawn wins whoever moves
+        assert!(probe(true, Square::E6, Square::E5, Square::E8));
+        assert!(probe(false, Square::E6, Square::E5, Square::E8));
+        // Opposition decides it with the king just in front on the fifth
+        assert!(probe(false, Square::E5, Square::E4, Square::E7));
+        assert!(!probe(true, Square::E5, Square::E4, Square::E7));
+        // Black king can't catch the pawn
+        assert!(probe(true, Square::A1, Square::D5, Square::H1));
+        // Rook pawn with the defending king in the corner
+        assert!(!probe(true, Square::H1, Square::A5, Square::A8));
+        assert!(!probe(true, Square::B6, Square::A6, Square::A8));
+        // Mirrored onto the queen side
+        assert!(probe(true, Square::D6, Square::D5, Square::D8) == probe(true, Square::E6, Square::E5, Square::E8));
+    }
+}
@@ -0,0 +1,20 @@
+// The engine as a library. Engine is the simplest way in, the modules are public for finer control
+pub mod engine;
+pub mod search;
+pub mod evaluation;
+pub mod score;
+pub mod transpo;
+pub mod history;
+pub mod time_management;
+pub mod perft;
+pub mod bench;
+pub mod book;
+pub mod pawns;
+mod move_ordering;
+mod bb_utils;
+mod see;
+mod pst;
+mod endgame;
+mod kpk;
+
+pub use engine::{Engine, SearchInfo, SearchLimits};
@@ -1,12 +1,14 @@
 mod uci;
-mod evaluation;
-mod search;
-mod transpo;
-mod move_ordering;
-mod bb_utils;
 
+use artemis::bench;
 
 fn main() {
+    // `artemis bench [depth]` runs the bench and exits, anything else starts the uci loop
+    let args : Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("bench") {
+        let depth = args.get(2).and_then(|depth| depth.parse().ok()).unwrap_or(bench::DEFAULT_BENCH_DEPTH);
+        bench::bench(u32::max(depth, 1));
+        return
+    }
     uci::uci_loop();
 }
-
@@ -2,7 +2,7 @@
 
 use chess::{ChessMove, Square, Board, MoveGen, NUM_SQUARES, NUM_COLORS, Color, EMPTY, NUM_PIECES};
 
-use crate::{transpo::TranspoTable, search::MAX_DEPTH, bb_utils::BitBoardUtils};
+use crate::{transpo::TranspoTable, search::MAX_DEPTH, bb_utils::BitBoardUtils, see::see};
 const DUMMY_MOVE : ChessMove = ChessMove {
     source: Square::A1,
     dest: Square::A1,
@@ -12,6 +12,8 @@ const MAX_MOVES : usize = 255;
 const NUM_KILLER_MOVES : usize = 2;
 const KILLER_MOVE_VALUE : i32 = 200000;
 const COUNTER_MOVE_BONUS : i32 = 200;
+// Captures that lose material go after every quiet move
+const LOSING_CAPTURE_VALUE : i32 = -1000000;
 // [captured][capturing]
 const MVV_LVA_VALUES : [[i32; NUM_PIECES]; NUM_PIECES] = [
     [100000, 110000, 120000, 130000, 140000, 150000],
@@ -23,7 +25,8 @@ const MVV_LVA_VALUES : [[i32; NUM_PIECES]; NUM_PIECES] = [
 ];
 
 pub struct MoveOrderer {
-    killer_moves : [[ChessMove; NUM_KILLER_MOVES]; MAX_DEPTH as usize],
+    // Indexed by depth left, a search to MAX_DEPTH starts with all of it
+    killer_moves : [[ChessMove; NUM_KILLER_MOVES]; MAX_DEPTH as usize + 1],
     history_info : [[[i32; NUM_SQUARES]; NUM_SQUARES]; NUM_COLORS],
     counter_move : [[ChessMove; NUM_SQUARES]; NUM_SQUARES],
     bb_utils : BitBoardUtils,
@@ -32,7 +35,7 @@ pub struct MoveOrderer {
 impl MoveOrderer {
     pub fn new() -> MoveOrderer {
         MoveOrderer {
-            killer_moves : [[DUMMY_MOVE; NUM_KILLER_MOVES]; MAX_DEPTH as usize],
+            killer_moves : [[DUMMY_MOVE; NUM_KILLER_MOVES]; MAX_DEPTH as usize + 1],
             history_info : [[[0; NUM_SQUARES]; NUM_SQUARES]; NUM_COLORS],
             counter_move : [[DUMMY_MOVE; NUM_SQUARES]; NUM_SQUARES],
             bb_utils : BitBoardUtils::new(),
@@ -71,7 +74,11 @@ impl MoveOrderer {
         sq_mask & enemy_pieces != EMPTY
     }
 
-    fn get_history(&self, board : &Board, chess_move : ChessMove) -> i32 {
+    pub fn is_killer(&self, depth : usize, chess_move : ChessMove) -> bool {
+        self.killer_moves[depth].contains(&chess_move)
+    }
+
+    pub fn get_history(&self, board : &Board, chess_move : ChessMove) -> i32 {
         let from = chess_move.get_source().to_index();
         let to = chess_move.get_dest().to_index();
 
@@ -83,12 +90,18 @@ impl MoveOrderer {
 pub struct MoveOrdering {
     moves : [ChessMove; MAX_MOVES],
     move_scores : [i32; MAX_MOVES],
+    // Whether each capture loses material. Moves are scored again on every pick, see only runs the first time
+    losing_captures : [Option<bool>; MAX_MOVES],
     num_moves : usize,
 }
 
 
 impl MoveOrdering {
     pub fn from_moves(gen : &mut MoveGen) -> MoveOrdering {
+        MoveOrdering::from_move_iter(gen)
+    }
+
+    pub fn from_move_iter(gen : impl Iterator<Item = ChessMove>) -> MoveOrdering {
         let mut moves = [DUMMY_MOVE; MAX_MOVES];
         let mut num_moves = 0;
         for chess_move in gen {
@@ -98,11 +111,13 @@ impl MoveOrdering {
         MoveOrdering {
             moves : moves,
             move_scores : [0; MAX_MOVES],
+            losing_captures : [None; MAX_MOVES],
             num_moves : num_moves
         }
     }
     
-    fn calculate_score(&self, chess_move : ChessMove, board : &Board, depth : usize, move_orderer : &MoveOrderer, last_move : ChessMove) -> i32{
+    fn calculate_score(&mut self, idx : usize, board : &Board, depth : usize, move_orderer : &MoveOrderer, last_move : ChessMove) -> i32{
+        let chess_move = self.moves[idx];
         for i in 0..NUM_KILLER_MOVES {
             if move_orderer.killer_moves[depth][i] == chess_move {
                 return KILLER_MOVE_VALUE
@@ -123,6 +138,11 @@ impl MoveOrdering {
             let captured_piece = board.piece_on(chess_move.get_dest()).unwrap();
             let capturing_piece = board.piece_on(chess_move.get_source()).unwrap();
             score += MVV_LVA_VALUES[captured_piece.to_index()][capturing_piece.to_index()];
+            // Only bother with see when the capturer is worth more than what it takes
+            if capturing_piece.to_index() > captured_piece.to_index()
+                && *self.losing_captures[idx].get_or_insert_with(|| see(board, chess_move) < 0) {
+                score += LOSING_CAPTURE_VALUE;
+            }
         }
 
         score
@@ -136,6 +156,7 @@ impl MoveOrdering {
         let temp = self.moves[i];
         self.moves[i] = self.moves[j];
         self.moves[j] = temp;
+        self.losing_captures.swap(i, j);
     }
 
     pub fn get_next_best_move(&mut self, moves_processed : usize, board : &Board, depth : usize, tt: &TranspoTable, move_orderer : &MoveOrderer, last_move : ChessMove) -> ChessMove {
@@ -159,7 +180,7 @@ impl MoveOrdering {
         let mut max_score = i32::MIN;
         let mut best_idx = moves_processed;
         for i in moves_processed..self.num_moves {
-            let score = self.calculate_score(self.moves[i], board, depth, move_orderer, last_move);
+            let score = self.calculate_score(i, board, depth, move_orderer, last_move);
             self.move_scores[i] = score;
             if score > max_score {
                 max_score = score;
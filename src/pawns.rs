@@ -0,0 +1,173 @@
+// Pawn structure evaluation
+// It only depends on where the pawns are, and pawns rarely move, so results are cached in a table keyed on
+// the pawn bitboards.
+use chess::{BitBoard, Board, Color, Piece, EMPTY, get_adjacent_files, get_file};
+use crate::bb_utils::{relative_rank, BitBoardUtils};
+use crate::score::Score;
+
+const PAWN_TABLE_SIZE : usize = 16384;
+// Mixes the two pawn bitboards into a table index
+const PAWN_HASH_MULTIPLIER : u64 = 0x9E3779B97F4A7C15;
+
+// All by the pawn's rank from its own side
+const PASSED_PAWN_BONUS : [Score; 8] = [Score::new(0, 0), Score::new(0, 5), Score::new(5, 10), Score::new(10, 20),
+    Score::new(20, 40), Score::new(35, 70), Score::new(55, 110), Score::new(0, 0)];
+// Not passed yet, but with enough friendly pawns around to force its way through
+const CANDIDATE_PASSER_BONUS : [Score; 8] = [Score::new(0, 0), Score::new(0, 3), Score::new(3, 5), Score::new(5, 10),
+    Score::new(10, 20), Score::new(18, 35), Score::new(0, 0), Score::new(0, 0)];
+// Defended by a pawn or standing next to one
+const CONNECTED_PAWN_BONUS : [Score; 8] = [Score::new(0, 0), Score::new(5, 2), Score::new(7, 4), Score::new(10, 7),
+    Score::new(15, 12), Score::new(25, 20), Score::new(40, 35), Score::new(0, 0)];
+const DOUBLED_PAWN_PENALTY : Score = Score::new(10, 25);
+const ISOLATED_PAWN_PENALTY : Score = Score::new(12, 15);
+// Can't be defended by another pawn and can't advance safely
+const BACKWARD_PAWN_PENALTY : Score = Score::new(10, 12);
+
+// Each side's pawn structure, kept apart by term for the eval trace
+#[derive(Clone, Copy, Default)]
+pub struct PawnTerms {
+    pub passed : Score,
+    pub candidate : Score,
+    pub connected : Score,
+    pub doubled : Score,
+    pub isolated : Score,
+    pub backward : Score,
+}
+
+#[derive(Clone, Copy)]
+pub struct PawnEntry {
+    white_pawns : BitBoard,
+    black_pawns : BitBoard,
+    // Indexed by colour, each from its own side's point of view
+    pub terms : [PawnTerms; 2],
+    // Passed pawns of both colours
+    pub passed : BitBoard,
+}
+
+// Empty until the first probe, evaluators that are handed a table from an earlier search never allocate their own
+#[derive(Default)]
+pub struct PawnTable {
+    entrys : Vec<Option<PawnEntry>>,
+}
+
+// Terms for color's pawns, and which of them are passed
+fn side_pawn_structure(our_pawns : BitBoard, enemy_pawns : BitBoard, color : Color, bb_utils : &BitBoardUtils) -> (PawnTerms, BitBoard) {
+    let our_attacks = bb_utils.pawn_attacks(our_pawns, color);
+    let enemy_attacks = bb_utils.pawn_attacks(enemy_pawns, !color);
+    let mut terms = PawnTerms::default();
+    let mut passed = EMPTY;
+
+    for square in our_pawns {
+        let square_bb = BitBoard::from_square(square);
+        let rank = square.get_rank().to_index();
+        let relative = relative_rank(color, rank);
+        let file = get_file(square.get_file());
+        let adjacent_files = get_adjacent_files(square.get_file());
+        let in_front = bb_utils.ranks_in_front(color, rank);
+        let stop_square = match color {
+            Color::White => BitBoard(square_bb.0 << 8),
+            Color::Black => BitBoard(square_bb.0 >> 8),
+        };
+
+        // Our own pawn in front blocks it as well, only the front pawn of a doubled pair can be passed
+        let blockers = (enemy_pawns | our_pawns) & file & in_front;
+        let sentries = enemy_pawns & adjacent_files & in_front;
+        let neighbours = our_pawns & adjacent_files;
+        let supporters = neighbours & !in_front;
+
+        if blockers == EMPTY && sentries == EMPTY {
+            terms.passed += PASSED_PAWN_BONUS[relative];
+            passed |= square_bb;
+        } else if blockers == EMPTY && supporters.popcnt() >= sentries.popcnt() {
+            terms.candidate += CANDIDATE_PASSER_BONUS[relative];
+        }
+
+        // Count each extra pawn on the file once, against the one furthest back
+        if (our_pawns & file & in_front) != EMPTY {
+            terms.doubled -= DOUBLED_PAWN_PENALTY;
+        }
+
+        if neighbours == EMPTY {
+            terms.isolated -= ISOLATED_PAWN_PENALTY;
+        } else if supporters == EMPTY && (stop_square & enemy_attacks) != EMPTY {
+            terms.backward -= BACKWARD_PAWN_PENALTY;
+        }
+
+        let phalanx = neighbours & bb_utils.rank_mask[rank];
+        if (our_attacks & square_bb) != EMPTY || phalanx != EMPTY {
+            terms.connected += CONNECTED_PAWN_BONUS[relative];
+        }
+    }
+    (terms, passed)
+}
+
+fn evaluate_pawns(white_pawns : BitBoard, black_pawns : BitBoard, bb_utils : &BitBoardUtils) -> PawnEntry {
+    let (white_terms, white_passed) = side_pawn_structure(white_pawns, black_pawns, Color::White, bb_utils);
+    let (black_terms, black_passed) = side_pawn_structure(black_pawns, white_pawns, Color::Black, bb_utils);
+    PawnEntry {
+        white_pawns : white_pawns,
+        black_pawns : black_pawns,
+        terms : [white_terms, black_terms],
+        passed : white_passed | black_passed,
+    }
+}
+
+impl PawnTable {
+    pub fn new() -> PawnTable {
+        PawnTable {
+            entrys : Vec::new(),
+        }
+    }
+
+    pub fn probe(&mut self, board : &Board, bb_utils : &BitBoardUtils) -> PawnEntry {
+        if self.entrys.is_empty() {
+            self.entrys = vec![None; PAWN_TABLE_SIZE];
+        }
+        let white_pawns = board.pieces(Piece::Pawn) & board.color_combined(Color::White);
+        let black_pawns = board.pieces(Piece::Pawn) & board.color_combined(Color::Black);
+        let key = (white_pawns.0 ^ black_pawns.0.rotate_left(32)).wrapping_mul(PAWN_HASH_MULTIPLIER);
+        let index = (key >> 32) as usize % PAWN_TABLE_SIZE;
+
+        // The whole key is stored, so a hit is never another pawn structure
+        if let Some(entry) = self.entrys[index] {
+            if entry.white_pawns == white_pawns && entry.black_pawns == black_pawns {
+                return entry
+            }
+        }
+        let entry = evaluate_pawns(white_pawns, black_pawns, bb_utils);
+        self.entrys[index] = Some(entry);
+        entry
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chess::Square;
+    use std::str::FromStr;
+
+    fn pawn_entry(fen : &str) -> PawnEntry {
+        let board = Board::from_str(fen).unwrap();
+        PawnTable::new().probe(&board, &BitBoardUtils::new())
+    }
+
+    #[test]
+    fn doubled_pawns_on_free_file() {
+        let entry = pawn_entry("4k3/p7/8/4P3/4P3/8/8/4K3 w - - 0 1");
+        // Only the front pawn is passed, the one behind it isn't a candidate either
+        assert_eq!(entry.passed & BitBoard::from_square(Square::E4), EMPTY);
+        assert_ne!(entry.passed & BitBoard::from_square(Square::E5), EMPTY);
+        let white = entry.terms[Color::White.to_index()];
+        assert_eq!(white.passed, PASSED_PAWN_BONUS[4]);
+        assert_eq!(white.candidate, Score::default());
+        assert_eq!(white.doubled, -DOUBLED_PAWN_PENALTY);
+    }
+
+    #[test]
+    fn doubled_pawns_black() {
+        let entry = pawn_entry("4k3/8/8/3p4/3p4/8/7P/4K3 b - - 0 1");
+        assert_eq!(entry.passed & BitBoard::from_square(Square::D5), EMPTY);
+        assert_ne!(entry.passed & BitBoard::from_square(Square::D4), EMPTY);
+        assert_eq!(entry.terms[Color::Black.to_index()].passed, PASSED_PAWN_BONUS[4]);
+    }
+}
@@ -0,0 +1,96 @@
+// Move generation testing. Counts the leaf nodes of the legal move tree to a fixed depth.
+use chess::{Board, MoveGen};
+use std::time::SystemTime;
+
+pub fn perft(board : &Board, depth : u32) -> u64 {
+    if depth == 0 {
+        return 1
+    }
+    let moves = MoveGen::new_legal(board);
+    // The moves at the last ply are leaves, no need to make them
+    if depth == 1 {
+        return moves.len() as u64
+    }
+    let mut total = 0;
+    for chess_move in moves {
+        total += perft(&board.make_move_new(chess_move), depth - 1);
+    }
+    total
+}
+
+// Perft split by root move, for finding which move a bad count comes from
+pub fn divide(board : &Board, depth : u32) -> u64 {
+    let start = SystemTime::now();
+    let mut total = 0;
+    if depth > 0 {
+        for chess_move in MoveGen::new_legal(board) {
+            let num = perft(&board.make_move_new(chess_move), depth - 1);
+            println!("{chess_move}: {num}");
+            total += num;
+        }
+    } else {
+        total = 1;
+    }
+    let duration_millis = u128::max(start.elapsed().unwrap().as_millis(), 1);
+    let nps = (total as f64 / (duration_millis as f64 / 1000.0)) as u64;
+
+    println!();
+    println!("Nodes searched: {total}");
+    println!("Took {}s. nps {nps}", duration_millis as f64 / 1000.0);
+    total
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    // Node counts from https://www.chessprogramming.org/Perft_Results
+    fn check_perft(fen : &str, expected : &[u64]) {
+        let board = Board::from_str(fen).unwrap();
+        for (depth, nodes) in expected.iter().enumerate() {
+            assert_eq!(perft(&board, depth as u32 + 1), *nodes, "{fen} depth {}", depth + 1);
+        }
+    }
+
+    #[test]
+    fn startpos() {
+        check_perft("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", &[20, 400, 8902, 197281]);
+    }
+
+    #[test]
+    fn kiwipete() {
+        check_perft("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1", &[48, 2039, 97862]);
+    }
+
+    #[test]
+    fn position_3() {
+        check_perft("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1", &[14, 191, 2812, 43238, 674624]);
+    }
+
+    #[test]
+    fn position_4() {
+        check_perft("r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1", &[6, 264, 9467, 422333]);
+    }
+
+    #[test]
+    fn position_4_mirrored() {
+        check_perft("r2q1rk1/pP1p2pp/Q4n2/bbp1p3/Np6/1B3NBn/pPPP1PPP/R3K2R b KQ - 0 1", &[6, 264, 9467, 422333]);
+    }
+
+    #[test]
+    fn position_5() {
+        check_perft("rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8", &[44, 1486, 62379]);
+    }
+
+    #[test]
+    fn position_6() {
+        check_perft("r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10", &[46, 2079, 89890]);
+    }
+
+    #[test]
+    fn divide_matches_perft() {
+        let board = Board::default();
+        assert_eq!(divide(&board, 3), perft(&board, 3));
+    }
+}
@@ -0,0 +1,136 @@
+// Piece square tables
+// Written from white's point of view with a1 first, so the board reads upside down. Black uses the same tables
+// mirrored vertically.
+use chess::{Color, Piece, Square, NUM_PIECES};
+use crate::score::Score;
+
+const PAWN_MIDGAME : [i32; 64] =
+    [  0,  0,  0,  0,  0,  0,  0,  0,
+       5, 10, 10,-20,-20, 10, 10,  5,
+       5, -5,-10,  0,  0,-10, -5,  5,
+       0,  0,  0, 20, 20,  0,  0,  0,
+       5,  5, 10, 25, 25, 10,  5,  5,
+      10, 10, 20, 30, 30, 20, 10, 10,
+      50, 50, 50, 50, 50, 50, 50, 50,
+       0,  0,  0,  0,  0,  0,  0,  0];
+// Further up the board is closer to promoting
+const PAWN_ENDGAME : [i32; 64] =
+    [  0,  0,  0,  0,  0,  0,  0,  0,
+       0,  0,  0,  0,  0,  0,  0,  0,
+       5,  5,  5,  5,  5,  5,  5,  5,
+      10, 10, 10, 10, 10, 10, 10, 10,
+      20, 20, 20, 20, 20, 20, 20, 20,
+      35, 35, 35, 35, 35, 35, 35, 35,
+      60, 60, 60, 60, 60, 60, 60, 60,
+       0,  0,  0,  0,  0,  0,  0,  0];
+
+const KNIGHT_MIDGAME : [i32; 64] =
+    [-50,-40,-30,-30,-30,-30,-40,-50,
+     -40,-20,  0,  5,  5,  0,-20,-40,
+     -30,  5, 10, 15, 15, 10,  5,-30,
+     -30,  0, 15, 20, 20, 15,  0,-30,
+     -30,  5, 15, 20, 20, 15,  5,-30,
+     -30,  0, 10, 15, 15, 10,  0,-30,
+     -40,-20,  0,  0,  0,  0,-20,-40,
+     -50,-40,-30,-30,-30,-30,-40,-50];
+const KNIGHT_ENDGAME : [i32; 64] =
+    [-40,-30,-20,-20,-20,-20,-30,-40,
+     -30,-15, -5,  0,  0, -5,-15,-30,
+     -20, -5,  5, 10, 10,  5, -5,-20,
+     -20,  0, 10, 15, 15, 10,  0,-20,
+     -20,  0, 10, 15, 15, 10,  0,-20,
+     -20, -5,  5, 10, 10,  5, -5,-20,
+     -30,-15, -5,  0,  0, -5,-15,-30,
+     -40,-30,-20,-20,-20,-20,-30,-40];
+
+const BISHOP_MIDGAME : [i32; 64] =
+    [-20,-10,-10,-10,-10,-10,-10,-20,
+     -10,  5,  0,  0,  0,  0,  5,-10,
+     -10, 10, 10, 10, 10, 10, 10,-10,
+     -10,  0, 10, 10, 10, 10,  0,-10,
+     -10,  5,  5, 10, 10,  5,  5,-10,
+     -10,  0,  5, 10, 10,  5,  0,-10,
+     -10,  0,  0,  0,  0,  0,  0,-10,
+     -20,-10,-10,-10,-10,-10,-10,-20];
+const BISHOP_ENDGAME : [i32; 64] =
+    [-15,-10, -8, -5, -5, -8,-10,-15,
+     -10, -5,  0,  0,  0,  0, -5,-10,
+      -8,  0,  5,  5,  5,  5,  0, -8,
+      -5,  0,  5, 10, 10,  5,  0, -5,
+      -5,  0,  5, 10, 10,  5,  0, -5,
+      -8,  0,  5,  5,  5,  5,  0, -8,
+     -10, -5,  0,  0,  0,  0, -5,-10,
+     -15,-10, -8, -5, -5, -8,-10,-15];
+
+const ROOK_MIDGAME : [i32; 64] =
+    [  0,  0,  0,  5,  5,  0,  0,  0,
+      -5,  0,  0,  0,  0,  0,  0, -5,
+      -5,  0,  0,  0,  0,  0,  0, -5,
+      -5,  0,  0,  0,  0,  0,  0, -5,
+      -5,  0,  0,  0,  0,  0,  0, -5,
+      -5,  0,  0,  0,  0,  0,  0, -5,
+       5,  5,  5,  5,  5,  5,  5,  5,
+       0,  0,  0,  0,  0,  0,  0,  0];
+const ROOK_ENDGAME : [i32; 64] =
+    [  0,  0,  0,  0,  0,  0,  0,  0,
+       0,  0,  0,  0,  0,  0,  0,  0,
+       0,  0,  0,  0,  0,  0,  0,  0,
+       0,  0,  0,  0,  0,  0,  0,  0,
+       0,  0,  0,  0,  0,  0,  0,  0,
+       0,  0,  0,  0,  0,  0,  0,  0,
+       5,  5,  5,  5,  5,  5,  5,  5,
+       0,  0,  0,  0,  0,  0,  0,  0];
+
+const QUEEN_MIDGAME : [i32; 64] =
+    [-20,-10,-10, -5, -5,-10,-10,-20,
+     -10,  0,  5,  0,  0,  0,  0,-10,
+     -10,  5,  5,  5,  5,  5,  0,-10,
+       0,  0,  5,  5,  5,  5,  0, -5,
+      -5,  0,  5,  5,  5,  5,  0, -5,
+     -10,  0,  5,  5,  5,  5,  0,-10,
+     -10,  0,  0,  0,  0,  0,  0,-10,
+     -20,-10,-10, -5, -5,-10,-10,-20];
+const QUEEN_ENDGAME : [i32; 64] =
+    [-20,-15,-10, -5, -5,-10,-15,-20,
+     -15, -5,  0,  0,  0,  0, -5,-15,
+     -10,  0,  5,  5,  5,  5,  0,-10,
+      -5,  0,  5, 10, 10,  5,  0, -5,
+      -5,  0,  5, 10, 10,  5,  0, -5,
+     -10,  0,  5,  5,  5,  5,  0,-10,
+     -15, -5,  0,  0,  0,  0, -5,-15,
+     -20,-15,-10, -5, -5,-10,-15,-20];
+
+// Tucked away behind its pawns while there are pieces around, in the middle of things once they're gone
+const KING_MIDGAME : [i32; 64] =
+    [ 20, 30, 10,  0,  0, 10, 30, 20,
+      20, 20,  0,  0,  0,  0, 20, 20,
+     -10,-20,-20,-20,-20,-20,-20,-10,
+     -20,-30,-30,-40,-40,-30,-30,-20,
+     -30,-40,-40,-50,-50,-40,-40,-30,
+     -30,-40,-40,-50,-50,-40,-40,-30,
+     -30,-40,-40,-50,-50,-40,-40,-30,
+     -30,-40,-40,-50,-50,-40,-40,-30];
+const KING_ENDGAME : [i32; 64] =
+    [-50,-30,-30,-30,-30,-30,-30,-50,
+     -30,-30,  0,  0,  0,  0,-30,-30,
+     -30,-10, 20, 30, 30, 20,-10,-30,
+     -30,-10, 30, 40, 40, 30,-10,-30,
+     -30,-10, 30, 40, 40, 30,-10,-30,
+     -30,-10, 20, 30, 30, 20,-10,-30,
+     -30,-20,-10,  0,  0,-10,-20,-30,
+     -50,-40,-30,-20,-20,-30,-40,-50];
+
+const MIDGAME_TABLES : [[i32; 64]; NUM_PIECES] =
+    [PAWN_MIDGAME, KNIGHT_MIDGAME, BISHOP_MIDGAME, ROOK_MIDGAME, QUEEN_MIDGAME, KING_MIDGAME];
+const ENDGAME_TABLES : [[i32; 64]; NUM_PIECES] =
+    [PAWN_ENDGAME, KNIGHT_ENDGAME, BISHOP_ENDGAME, ROOK_ENDGAME, QUEEN_ENDGAME, KING_ENDGAME];
+
+// The bonus for a piece of color standing on square, from that color's point of view
+pub fn piece_square(piece : Piece, color : Color, square : Square) -> Score {
+    let index = match color {
+        Color::White => square.to_index(),
+        // Flips the rank, a8 becomes a1
+        Color::Black => square.to_index() ^ 56,
+    };
+    Score::new(MIDGAME_TABLES[piece.to_index()][index], ENDGAME_TABLES[piece.to_index()][index])
+}
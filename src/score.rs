@@ -0,0 +1,75 @@
+// Tapered evaluation. Every eval term has a midgame and an endgame value, and the two are blended by how
+// much material is left on the board.
+use chess::{Board, Piece, NUM_PIECES};
+use std::ops::{Add, AddAssign, Mul, Neg, Sub, SubAssign};
+
+// Phase of the starting position, the midgame value counts fully here
+pub const MAX_PHASE : i32 = 24;
+// How much each piece adds to the phase. Pawns and kings never leave, so they don't tell us anything
+const PHASE_WEIGHTS : [i32; NUM_PIECES] = [0, 1, 1, 2, 4, 0];
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct Score {
+    pub mg : i32,
+    pub eg : i32,
+}
+
+impl Score {
+    pub const fn new(mg : i32, eg : i32) -> Score {
+        Score { mg : mg, eg : eg }
+    }
+
+    // phase runs from 0 in a bare endgame to MAX_PHASE with all pieces on
+    pub fn taper(self, phase : i32) -> i32 {
+        (self.mg * phase + self.eg * (MAX_PHASE - phase)) / MAX_PHASE
+    }
+}
+
+// From the pieces left on the board. Promotions can push the sum past MAX_PHASE
+pub fn game_phase(board : &Board) -> i32 {
+    let mut phase = 0;
+    for piece in [Piece::Knight, Piece::Bishop, Piece::Rook, Piece::Queen] {
+        phase += PHASE_WEIGHTS[piece.to_index()] * board.pieces(piece).popcnt() as i32;
+    }
+    i32::min(phase, MAX_PHASE)
+}
+
+impl Add for Score {
+    type Output = Score;
+    fn add(self, other : Score) -> Score {
+        Score::new(self.mg + other.mg, self.eg + other.eg)
+    }
+}
+
+impl Sub for Score {
+    type Output = Score;
+    fn sub(self, other : Score) -> Score {
+        Score::new(self.mg - other.mg, self.eg - other.eg)
+    }
+}
+
+impl AddAssign for Score {
+    fn add_assign(&mut self, other : Score) {
+        *self = *self + other;
+    }
+}
+
+impl SubAssign for Score {
+    fn sub_assign(&mut self, other : Score) {
+        *self = *self - other;
+    }
+}
+
+impl Neg for Score {
+    type Output = Score;
+    fn neg(self) -> Score {
+        Score::new(-self.mg, -self.eg)
+    }
+}
+
+impl Mul<i32> for Score {
+    type Output = Score;
+    fn mul(self, scale : i32) -> Score {
+        Score::new(self.mg * scale, self.eg * scale)
+    }
+}
@@ -1,17 +1,31 @@
 use chess::Board;
 use chess::MoveGen;
 use chess::ChessMove;
-use chess::{Square, Color, EMPTY};
+use chess::{BitBoard, Color, Piece, Square, EMPTY};
+use std::mem;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread;
 use std::time::{SystemTime, Duration};
 use vampirc_uci::{UciTimeControl, UciSearchControl};
 use crate::bb_utils::BitBoardUtils;
-use crate::evaluation::{Evaluator, NAIVE_PIECE_VAL};
+use crate::evaluation::{eval_is_mate, Evaluator, NAIVE_PIECE_VAL};
+use crate::history::PositionHistory;
+use crate::pawns::PawnTable;
+use crate::see::see;
+use crate::time_management::{TimeManager, DEFAULT_MOVE_OVERHEAD_MS};
 use crate::move_ordering::{MoveOrderer, MoveOrdering};
-use crate::transpo::{TranspoTable, EntryFlags};
+use crate::transpo::{TranspoTable, TableEntry, EntryFlags};
 
 const MIN_ALPHA : i32 = i32::MIN + 500;
 const MAX_BETA : i32 = i32::MAX - 500;
 pub const MAX_DEPTH : u32 = 200;
+// Nothing is searched deeper than MAX_DEPTH, so no pv can be longer
+const MAX_PV_LENGTH : usize = MAX_DEPTH as usize;
+pub const MAX_THREADS : usize = 256;
+pub const MAX_MULTI_PV : usize = 256;
+// The search recurses deeply and keeps its move lists on the stack
+pub const SEARCH_THREAD_STACK_SIZE : usize = 64 * 1024 * 1024;
 pub const DUMMY_MOVE : ChessMove = ChessMove {
         source: Square::A1,
         dest: Square::A1,
@@ -21,11 +35,35 @@ const FUTILITY_PRUNE_DEPTH : i32 = 3;
 const FUTILITY_VALUES : [i32; (FUTILITY_PRUNE_DEPTH+1) as usize] = [0, 200, 300, 500];
 
 
+// Late move reductions
+const LMR_MIN_DEPTH : i32 = 3;
+const LMR_MIN_MOVES : usize = 3;
+const LMR_TABLE_SIZE : usize = 64;
+// Quiet moves with at least this much history have earned a smaller reduction
+const LMR_GOOD_HISTORY : i32 = 1000;
+
+// Singular extensions
+const SINGULAR_MIN_DEPTH : i32 = 8;
+// How much shallower than us the tt entry may be and still be trusted
+const SINGULAR_TT_DEPTH_MARGIN : i32 = 3;
+// The other moves have to be this many centipawns per ply of depth worse than the tt move
+const SINGULAR_MARGIN_PER_DEPTH : i32 = 2;
+
 const NULL_MOVE_MIN_REDUCTION : i32 = 3;
 const DELTA_PRUNE_MAX : i32 = 900;
 const DELTA_PRUNE_MATERIAL_CUTOFF : i32 = 1600;
+// Search quiet checks at the first ply of quiescence
+const QUIESCE_CHECKS : bool = true;
 pub struct Cfg {
     depth_left : u32,
+    start_depth : u32,
+    num_threads : usize,
+    multi_pv : usize,
+    move_overhead : Duration,
+    node_limit : Option<u64>,
+    mate_limit : Option<u8>,
+    // Only these root moves are searched if it isn't empty
+    search_moves : Vec<ChessMove>,
 }
 
 struct AlphabetaInfo {
@@ -34,6 +72,8 @@ struct AlphabetaInfo {
     depth_left : i32,
     ply : u32,
     last_move : ChessMove,
+    // Searched as if this move didn't exist, for singular extensions
+    excluded_move : Option<ChessMove>,
 }
 
 struct SearchResult {
@@ -42,90 +82,123 @@ struct SearchResult {
 
 struct Line {
     cmove : u32,
-    chess_move : [ChessMove; 100], //todo unsafe uninit
+    chess_move : [ChessMove; MAX_PV_LENGTH], //todo unsafe uninit
 }
 
 pub struct Search {
     cfg : Cfg,
     time_control : Option<UciTimeControl>,
-    _search_control : Option<UciSearchControl>,
-    end_time : SystemTime,
+    time_manager : TimeManager,
     is_following_pv : bool,
     in_null_move_prune : bool,
-    nodes_evaled : u32,
+    nodes_evaled : u64,
     past_end_time : bool,
+    stop : Arc<AtomicBool>,
+    // Set while the GUI has us thinking on the opponent's time, cleared on ponderhit
+    pondering : Arc<AtomicBool>,
+    // Whether the clock is waiting for the ponder move to be played
+    waiting_for_ponder_hit : bool,
+    ponder_move : Option<ChessMove>,
+    // The pv and score of the last finished iteration
+    pv : Vec<ChessMove>,
+    score : i32,
+    // Whether to print info lines, library users may not want them
+    report_info : bool,
+    // Lazy SMP. Only the main thread reports, the helpers publish their node counts for it to add up
+    is_main_thread : bool,
+    node_counter : Arc<AtomicU64>,
+    helper_node_counters : Vec<Arc<AtomicU64>>,
+    // What the helper threads searched in total, once they're done
+    helper_nodes : u64,
+    // The depth of the current iteration
+    root_depth : u32,
+    // Root moves already reported as a better pv in multipv mode
+    excluded_root_moves : Vec<ChessMove>,
+    history : PositionHistory,
     move_orderer : MoveOrderer,
     evaluator : Evaluator,
+    // One for each thread, kept by the caller between searches so they don't start out empty
+    pawn_tables : Arc<Mutex<Vec<PawnTable>>>,
     bb_utils : BitBoardUtils,
+    // [depth][moves searched]
+    lmr_table : [[i32; LMR_TABLE_SIZE]; LMR_TABLE_SIZE],
 }
 impl Search {
     pub fn new() -> Search {
         Search {
             cfg : Cfg{
                 depth_left : 0,
+                start_depth : 1,
+                num_threads : 1,
+                multi_pv : 1,
+                move_overhead : Duration::from_millis(DEFAULT_MOVE_OVERHEAD_MS),
+                node_limit : None,
+                mate_limit : None,
+                search_moves : Vec::new(),
             },
-            end_time : SystemTime::now() + Duration::new(i32::MAX as u64, 0),
+            time_manager : TimeManager::infinite(),
             time_control : None,
-            _search_control : None,
             is_following_pv : false,
             in_null_move_prune : false,
             nodes_evaled : 0,
             past_end_time : false,
+            stop : Arc::new(AtomicBool::new(false)),
+            pondering : Arc::new(AtomicBool::new(false)),
+            waiting_for_ponder_hit : false,
+            ponder_move : None,
+            pv : Vec::new(),
+            score : 0,
+            report_info : true,
+            is_main_thread : true,
+            node_counter : Arc::new(AtomicU64::new(0)),
+            helper_node_counters : Vec::new(),
+            helper_nodes : 0,
+            root_depth : 0,
+            excluded_root_moves : Vec::new(),
+            history : PositionHistory::new(&Board::default(), 0),
             move_orderer : MoveOrderer::new(),
             evaluator : Evaluator::new(),
+            pawn_tables : Arc::new(Mutex::new(Vec::new())),
             bb_utils : BitBoardUtils::new(),
+            lmr_table : Search::build_lmr_table(),
         }
     }
 
-    fn calculate_expected_ply(&self, board : &Board) -> u32 {
-        100
+    // Reductions grow with both depth and how far down the move list we are
+    fn build_lmr_table() -> [[i32; LMR_TABLE_SIZE]; LMR_TABLE_SIZE] {
+        let mut table = [[0; LMR_TABLE_SIZE]; LMR_TABLE_SIZE];
+        for (depth, row) in table.iter_mut().enumerate().skip(1) {
+            for (moves_searched, reduction) in row.iter_mut().enumerate().skip(1) {
+                *reduction = (0.75 + (depth as f64).ln() * (moves_searched as f64).ln() / 2.25) as i32;
+            }
+        }
+        table
     }
 
-    fn calculate_end_time(&mut self, board : &Board) {
+    // Also decides how deep to go when we weren't given a depth
+    fn start_time_manager(&mut self, board : &Board) {
+        // A mate in N moves is at most 2N plies away
+        let mate_depth = self.cfg.mate_limit.map(|mate_limit| u32::max(2 * mate_limit as u32, 1));
+        // Fixed depth searches ignore the clock
         if self.cfg.depth_left != 0 {
+            if let Some(mate_depth) = mate_depth {
+                self.cfg.depth_left = u32::min(self.cfg.depth_left, mate_depth);
+            }
             return
         }
-        if self.time_control.is_some() {
-            let now = SystemTime::now();
-            let mut end_time = now + Duration::new(i32::MAX as u64, 0);
-            match self.time_control.as_ref().unwrap() {
-                UciTimeControl::MoveTime (duration) => {
-                    end_time = SystemTime::now() + duration.to_std().expect("Out of range");
-                },
-                UciTimeControl::Infinite => (),
-                UciTimeControl::TimeLeft { white_time, black_time, 
-                    white_increment, black_increment, .. } =>
-                    {
-                        let mut time_left = Duration::new(0, 0);
-                        let mut increment = Duration::new(0, 0);
-                        match board.side_to_move() {
-                            Color::White => {
-                                if white_time.is_some() {
-                                    time_left = white_time.unwrap().to_std().expect("Out of range");
-                                }
-                                if white_increment.is_some() {
-                                    increment = white_increment.unwrap().to_std().expect("Bad");
-                                }
-                            },
-                            Color::Black => {
-                                if black_time.is_some() {
-                                    time_left = black_time.unwrap().to_std().expect("Out of range");
-                                }
-                                if white_increment.is_some() {
-                                    increment = black_increment.unwrap().to_std().expect("Bad");
-                                }
-                            },
-                        }
-                        let num_moves = self.calculate_expected_ply(board);
-                        let ply_num = 0;
-                        let moves_to_go = (num_moves - ply_num) / 2;
-                        let move_time = time_left/moves_to_go + increment;
-                        end_time = now + move_time;
-                   }
-                _ => ()
-            }
-            self.end_time = end_time;
-            self.cfg.depth_left = u32::MAX;
+        if let Some(time_control) = self.time_control.as_ref() {
+            self.time_manager = TimeManager::new(time_control, board.side_to_move(), self.cfg.move_overhead);
+        }
+        // The clock, node and mate limits end these searches on their own
+        if self.time_control.is_some() || self.cfg.node_limit.is_some() || mate_depth.is_some() {
+            self.cfg.depth_left = mate_depth.unwrap_or(MAX_DEPTH);
+        }
+    }
+
+    fn check_ponder_hit(&mut self) {
+        if self.waiting_for_ponder_hit && !self.pondering.load(Ordering::Relaxed) {
+            self.time_manager.restart_clock();
+            self.waiting_for_ponder_hit = false;
         }
     }
 
@@ -133,33 +206,197 @@ impl Search {
         self.time_control = Some(time_controls);
     }
     
-    pub fn _set_search_controls(&mut self, search_controls : Option<UciSearchControl>) {
-        self._search_control = search_controls;
+    pub fn set_search_controls(&mut self, search_controls : UciSearchControl) {
+        if let Some(depth) = search_controls.depth {
+            self.set_cfg_depth(depth as u32);
+        }
+        self.cfg.node_limit = search_controls.nodes;
+        self.cfg.mate_limit = search_controls.mate;
+        self.cfg.search_moves = search_controls.search_moves;
     }
 
     pub fn set_cfg_depth(&mut self, depth: u32){
         self.cfg.depth_left = depth;
     }
 
+    pub fn set_threads(&mut self, num_threads : usize) {
+        self.cfg.num_threads = num_threads.clamp(1, MAX_THREADS);
+    }
+
+    // Time lost to communication with the GUI on every move
+    pub fn set_move_overhead(&mut self, move_overhead : Duration) {
+        self.cfg.move_overhead = move_overhead;
+    }
+
+    pub fn set_multi_pv(&mut self, multi_pv : usize) {
+        self.cfg.multi_pv = multi_pv.clamp(1, MAX_MULTI_PV);
+    }
+
+    // Searches sharing these reuse each other's pawn tables, one search at a time
+    pub fn set_pawn_tables(&mut self, pawn_tables : Arc<Mutex<Vec<PawnTable>>>) {
+        self.pawn_tables = pawn_tables;
+    }
+
+    // The flag is shared with the UCI thread, which sets it on stop/quit
+    pub fn set_stop_flag(&mut self, stop : Arc<AtomicBool>) {
+        self.stop = stop;
+    }
+
+    // The game so far, ending with the position we are searching
+    pub fn set_history(&mut self, history : PositionHistory) {
+        self.history = history;
+    }
+
+    pub fn set_ponder_flag(&mut self, pondering : Arc<AtomicBool>) {
+        self.pondering = pondering;
+    }
+
+    // The second move of the pv from the last search, if we have one
+    pub fn get_ponder_move(&self) -> Option<ChessMove> {
+        self.ponder_move
+    }
+
+    // Nodes searched by this thread in the last search
+    // Summed over every thread
+    pub fn get_nodes(&self) -> u64 {
+        self.nodes_evaled + self.helper_nodes
+    }
+
+    pub fn get_pv(&self) -> &[ChessMove] {
+        &self.pv
+    }
+
+    // In centipawns from the side to move's point of view
+    pub fn get_score(&self) -> i32 {
+        self.score
+    }
+
+    pub fn set_report_info(&mut self, report_info : bool) {
+        self.report_info = report_info;
+    }
+
+    fn should_stop(&self) -> bool {
+        if let Some(node_limit) = self.cfg.node_limit {
+            if self.total_nodes() >= node_limit {
+                return true
+            }
+        }
+        if !self.waiting_for_ponder_hit && self.time_manager.should_abort() {
+            return true
+        }
+        self.stop.load(Ordering::Relaxed)
+    }
+
+    fn found_mate_limit(&self, eval : i32) -> bool {
+        match self.cfg.mate_limit {
+            Some(mate_limit) => {
+                let distance = self.evaluator.eval_distance_to_mate(eval);
+                eval_is_mate(eval) && distance > 0 && distance <= mate_limit as i32
+            },
+            None => false,
+        }
+    }
+
+    fn is_excluded_root_move(&self, chess_move : ChessMove) -> bool {
+        if self.excluded_root_moves.contains(&chess_move) {
+            return true
+        }
+        !self.cfg.search_moves.is_empty() && !self.cfg.search_moves.contains(&chess_move)
+    }
+
     fn should_null_move_prune(&self, board : &Board, depth : i32) -> bool {
-        if !self.in_null_move_prune {
-            if self.evaluator.total_material_eval(board) > 1000 { // Endgames can lead to zugzwang
-                if (*board.checkers()) == EMPTY {
-                    if depth > NULL_MOVE_MIN_REDUCTION {
-                        return true
-                    }
+        // Endgames can lead to zugzwang
+        !self.in_null_move_prune && self.evaluator.total_material_eval(board) > 1000
+            && (*board.checkers()) == EMPTY && depth > NULL_MOVE_MIN_REDUCTION
+    }
+
+    pub fn get_best_move(&mut self, board : &Board, tt : &TranspoTable) -> ChessMove {  
+        // Ignore searchmoves that aren't legal here, and the restriction entirely if none are
+        self.cfg.search_moves.retain(|chess_move| board.legal(*chess_move));
+        if !self.history.ends_with(board) {
+            self.history = PositionHistory::new(board, 0);
+        }
+        self.start_time_manager(board);
+        self.waiting_for_ponder_hit = self.pondering.load(Ordering::Relaxed);
+        self.helper_nodes = 0;
+
+        // Tables left over from more threads than we have now are dropped
+        let mut pawn_tables = mem::take(&mut *self.pawn_tables.lock().unwrap());
+        pawn_tables.resize_with(self.cfg.num_threads, PawnTable::new);
+        self.evaluator.set_pawn_table(pawn_tables.pop().unwrap());
+        let best_move = if self.cfg.num_threads <= 1 {
+            self.iterative_deepening(board, tt).0
+        } else {
+            self.lazy_smp_search(board, tt, &mut pawn_tables)
+        };
+        pawn_tables.push(self.evaluator.take_pawn_table());
+        *self.pawn_tables.lock().unwrap() = pawn_tables;
+
+        // The GUI doesn't want a bestmove while we ponder, even if the search is done
+        while self.pondering.load(Ordering::Relaxed) && !self.stop.load(Ordering::Relaxed) {
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        // The pv can get cut short by a tt hit, fall back on the tt for the reply we expect
+        if board.legal(best_move) {
+            let next_board = board.make_move_new(best_move);
+            if !self.ponder_move.is_some_and(|ponder_move| next_board.legal(ponder_move)) {
+                let entry = tt.probe(next_board.get_hash());
+                if entry.hash == next_board.get_hash() && next_board.legal(entry.best_move) {
+                    self.ponder_move = Some(entry.best_move);
+                } else {
+                    self.ponder_move = None;
                 }
             }
         }
-        false
+        best_move
+    }
+
+    fn lazy_smp_search(&mut self, board : &Board, tt : &TranspoTable, pawn_tables : &mut Vec<PawnTable>) -> ChessMove {
+        // Helpers search the same position until the main thread is done, sharing what they find through the tt
+        let helper_stop = Arc::new(AtomicBool::new(false));
+        let mut helpers : Vec<Search> = (1..self.cfg.num_threads).map(|i| self.new_helper(i, &helper_stop)).collect();
+        for helper in helpers.iter_mut() {
+            helper.evaluator.set_pawn_table(pawn_tables.pop().unwrap());
+        }
+        self.helper_node_counters = helpers.iter().map(|helper| Arc::clone(&helper.node_counter)).collect();
+
+        let best_move = thread::scope(|scope| {
+            for helper in helpers.iter_mut() {
+                thread::Builder::new()
+                    .stack_size(SEARCH_THREAD_STACK_SIZE)
+                    .spawn_scoped(scope, move || {
+                        helper.iterative_deepening(board, tt);
+                    })
+                    .expect("Failed to spawn helper thread");
+            }
+            let best_move = self.iterative_deepening(board, tt).0;
+            helper_stop.store(true, Ordering::Relaxed);
+            best_move
+        });
+        self.helper_node_counters.clear();
+        self.helper_nodes = helpers.iter().map(|helper| helper.nodes_evaled).sum();
+        pawn_tables.extend(helpers.iter_mut().map(|helper| helper.evaluator.take_pawn_table()));
+        best_move
     }
 
-    pub fn get_best_move(&mut self, board : &Board, tt : &mut TranspoTable) -> ChessMove {  
-        self.calculate_end_time(board);
-        return self.iterative_deepening(board, tt).0;
+    fn new_helper(&self, index : usize, stop : &Arc<AtomicBool>) -> Search {
+        let mut helper = Search::new();
+        helper.cfg.depth_left = MAX_DEPTH;
+        // Start every other helper a ply deeper so the threads don't all search the same tree in lockstep
+        helper.cfg.start_depth = 1 + (index % 2) as u32;
+        helper.cfg.search_moves = self.cfg.search_moves.clone();
+        helper.history = self.history.clone();
+        helper.stop = Arc::clone(stop);
+        helper.is_main_thread = false;
+        helper
     }
 
-    fn aspirated_search(&mut self, board : &Board, last_eval : i32, depth : i32, pv_line : &mut Line ,tt : &mut TranspoTable) -> i32{
+    fn total_nodes(&self) -> u64 {
+        self.nodes_evaled + self.helper_node_counters.iter().map(|counter| counter.load(Ordering::Relaxed)).sum::<u64>()
+    }
+
+    fn aspirated_search(&mut self, board : &Board, last_eval : i32, depth : i32, pv_line : &mut Line ,tt : &TranspoTable) -> i32{
         // For some reason this is not working at all :(
         let window_radius = 50;
         let aspirated_ab_info = AlphabetaInfo{
@@ -168,6 +405,7 @@ impl Search {
             depth_left : depth as i32,
             ply : 0,
             last_move : DUMMY_MOVE,
+            excluded_move : None,
         };
         let windowed_eval = self.alphabeta(board, &aspirated_ab_info, pv_line, tt).eval;
         let eval;
@@ -180,6 +418,7 @@ impl Search {
                 depth_left : depth as i32,
                 ply : 0,
                 last_move : DUMMY_MOVE,
+                excluded_move : None,
             };
             eval = self.alphabeta(board, &full_alpha_beta_range, pv_line, tt).eval;
         } else {
@@ -188,67 +427,120 @@ impl Search {
         eval
     }
 
-    fn iterative_deepening(&mut self, board : &Board, tt : &mut TranspoTable) -> (ChessMove, i32) {
+    fn print_info(&self, depth : u32, multi_pv_index : usize, eval : i32, pv_line : &Line, search_start_time : SystemTime) {
+        let search_duration = search_start_time.elapsed().unwrap();
+        let mut pv_string = String::new();
+        
+        for i in 0..pv_line.cmove {
+            pv_string.push_str(&pv_line.chess_move[i as usize].to_string());
+            pv_string.push(' ');
+        }
+
+        print!("info depth {depth} ");
+        if self.cfg.multi_pv > 1 {
+            print!("multipv {} ", multi_pv_index + 1);
+        }
+        print!("score ");
+        if eval_is_mate(eval) {
+            print!("mate {} ", self.evaluator.eval_distance_to_mate(eval));
+        } else {
+            print!("cp {eval} ");
+        }
+        let nodes = self.total_nodes();
+        let duration_millis = u128::max(search_duration.as_millis(), 1);
+        
+
+        let nps = (nodes as f64 / (duration_millis as f64 / 1000.0)) as u64;
+
+        println!("time {duration_millis} nodes {nodes} pv {pv_string} nps {nps}");
+    }
+
+    fn iterative_deepening(&mut self, board : &Board, tt : &TranspoTable) -> (ChessMove, i32) {
         self.nodes_evaled = 0;
+        self.pv.clear();
+        self.score = 0;
         let mut best_move : ChessMove = DUMMY_MOVE;
         let mut eval: i32 = 0;
         let search_start_time: SystemTime = SystemTime::now();
+        let num_root_moves = MoveGen::new_legal(board).filter(|chess_move| !self.is_excluded_root_move(*chess_move)).count();
+        let num_pvs = usize::min(self.cfg.multi_pv, num_root_moves);
 
-        for depth in 1..=self.cfg.depth_left {
+        for depth in self.cfg.start_depth..=self.cfg.depth_left {
             if depth > MAX_DEPTH {
                 break;
             }
-            let mut pv_line = Line {
-                cmove : 0,
-                chess_move : [DUMMY_MOVE; 100],
-            };
-
-            let full_alpha_beta_range = AlphabetaInfo {
-                alpha : MIN_ALPHA,
-                beta : MAX_BETA,
-                depth_left : depth as i32,
-                ply : 0,
-                last_move : DUMMY_MOVE, 
-            };
-            
-            // At the start we follow the pv so we can display the whole thing 
-            self.is_following_pv = true;
-
-            // Aspiration window here
-            if depth > 1 {
-                eval = self.aspirated_search(board, eval, depth as i32, &mut pv_line, tt);
-            } else {
-                let result = self.alphabeta(board, &full_alpha_beta_range, &mut pv_line, tt);
-                eval = result.eval;
+            self.check_ponder_hit();
+            self.root_depth = depth;
+
+            // Each extra pv is a search of the root without the moves the earlier pvs started with
+            self.excluded_root_moves.clear();
+            let mut lines : Vec<(i32, Line)> = Vec::with_capacity(num_pvs);
+            let mut stopped = false;
+            for multi_pv_index in 0..num_pvs {
+                let mut pv_line = Line {
+                    cmove : 0,
+                    chess_move : [DUMMY_MOVE; MAX_PV_LENGTH],
+                };
+
+                let full_alpha_beta_range = AlphabetaInfo {
+                    alpha : MIN_ALPHA,
+                    beta : MAX_BETA,
+                    depth_left : depth as i32,
+                    ply : 0,
+                    last_move : DUMMY_MOVE,
+                    excluded_move : None,
+                };
+                
+                // At the start we follow the pv so we can display the whole thing 
+                self.is_following_pv = true;
+
+                // Aspiration window here
+                let pv_eval = if depth > 1 && multi_pv_index == 0 {
+                    self.aspirated_search(board, eval, depth as i32, &mut pv_line, tt)
+                } else {
+                    self.alphabeta(board, &full_alpha_beta_range, &mut pv_line, tt).eval
+                };
+                self.node_counter.store(self.nodes_evaled, Ordering::Relaxed);
+                // Don't trust the results of an iteration we cut short, but always finish the first one so we have a move
+                if depth > self.cfg.start_depth && (self.past_end_time || self.should_stop()) {
+                    stopped = true;
+                    break;
+                }
+                self.excluded_root_moves.push(pv_line.chess_move[0]);
+                lines.push((pv_eval, pv_line));
             }
-            if SystemTime::now() > self.end_time {
+            if lines.is_empty() {
                 break;
             }
 
-            let search_duration = search_start_time.elapsed().unwrap();
+            // Later pvs can still come back with a better score than the ones searched before them
+            lines.sort_by(|(score, _), (other_score, _)| other_score.cmp(score));
+            let (pv_eval, pv_line) = &lines[0];
             best_move = pv_line.chess_move[0];
-            
-            let mut pv_string = String::new();
-            
-            for i in 0..pv_line.cmove {
-                pv_string.push_str(&pv_line.chess_move[i as usize].to_string());
-                pv_string.push_str(" ");
+            eval = *pv_eval;
+            self.ponder_move = if pv_line.cmove > 1 { Some(pv_line.chess_move[1]) } else { None };
+            self.pv = pv_line.chess_move[..pv_line.cmove as usize].to_vec();
+            self.score = *pv_eval;
+            if self.is_main_thread && self.report_info {
+                for (multi_pv_index, (pv_eval, pv_line)) in lines.iter().enumerate() {
+                    self.print_info(depth, multi_pv_index, *pv_eval, pv_line, search_start_time);
+                }
             }
-
-            print!("info depth {depth} score ");
-            if self.evaluator.eval_is_mate(eval) {
-                print!("mate {} ", self.evaluator.eval_distance_to_mate(eval));
-            } else {
-                print!("cp {eval} ");
+            if stopped || self.found_mate_limit(eval) {
+                break;
+            }
+            // Iterations take longer each time, don't start one we won't finish
+            if self.is_main_thread && !self.waiting_for_ponder_hit && !self.time_manager.should_start_iteration(best_move, eval) {
+                break;
+            }
+        }
+        self.excluded_root_moves.clear();
+        // We were stopped before depth 1 could produce a pv, still answer with a legal move
+        if best_move == DUMMY_MOVE {
+            if let Some(chess_move) = MoveGen::new_legal(board).find(|chess_move| !self.is_excluded_root_move(*chess_move)) {
+                best_move = chess_move;
+                self.pv = vec![chess_move];
             }
-            let nodes = self.nodes_evaled;
-            let duration_millis = u128::max(search_duration.as_millis(), 1);
-            
-
-            let nps = (nodes as f64 / (duration_millis as f64 / 1000.0)) as u64;
-
-            println!("time {duration_millis} nodes {nodes} pv {pv_string} nps {nps}");
-            
         }
         self.past_end_time = false;
         self.move_orderer.reset_history();
@@ -262,21 +554,17 @@ impl Search {
     }
 
     fn should_futility_prune_position(&self, board : &Board, depth : i32, ply : u32, beta : i32, eval : i32) -> bool {
-        if depth <= FUTILITY_PRUNE_DEPTH && ply > 1 {
-            if (*board.checkers()) == EMPTY {
-                return eval - FUTILITY_VALUES[depth as usize] > beta; 
-            }
+        if depth <= FUTILITY_PRUNE_DEPTH && ply > 1 && (*board.checkers()) == EMPTY {
+            return eval - FUTILITY_VALUES[depth as usize] > beta; 
         }
         false
     }
 
-    fn should_futility_prune_move(&self, board : &Board, is_move_check : bool, chess_move : ChessMove, index : usize, 
-        depth : i32, ply : u32, alpha : i32, position_eval : i32) -> bool {
-        if depth > FUTILITY_PRUNE_DEPTH  || ply < 1 || (*board.checkers()) == EMPTY {
-            return false
-        }
-
-        if index == 0  {
+    fn should_futility_prune_move(&self, board : &Board, alpha_beta_info : &AlphabetaInfo, is_move_check : bool,
+        chess_move : ChessMove, alpha : i32, position_eval : i32) -> bool {
+        let depth = alpha_beta_info.depth_left;
+        // Evasions are never pruned, the eval doesn't mean much while in check
+        if depth > FUTILITY_PRUNE_DEPTH  || alpha_beta_info.ply < 1 || (*board.checkers()) != EMPTY {
             return false
         }
 
@@ -290,91 +578,192 @@ impl Search {
             return false
         }
 
-        // TODO see
-        if self.is_capture(board, chess_move) {
+        // Winning and even captures are worth a look, losing ones can be pruned like quiet moves
+        if self.is_capture(board, chess_move) && see(board, chess_move) >= 0 {
             return false
         }
         position_eval + FUTILITY_VALUES[depth as usize] < alpha        
     }
 
+    fn late_move_reduction(&self, board : &Board, is_move_check : bool, chess_move : ChessMove, moves_searched : usize,
+        depth : i32, is_pv_node : bool) -> i32 {
+        if depth < LMR_MIN_DEPTH || moves_searched < LMR_MIN_MOVES || is_move_check || (*board.checkers()) != EMPTY {
+            return 0
+        }
+        if chess_move.get_promotion().is_some() {
+            return 0
+        }
+        // Like futility pruning, only losing captures get treated as quiet moves
+        let is_capture = self.is_capture(board, chess_move);
+        if is_capture && see(board, chess_move) >= 0 {
+            return 0
+        }
+
+        let mut reduction = self.lmr_table[usize::min(depth as usize, LMR_TABLE_SIZE - 1)][usize::min(moves_searched, LMR_TABLE_SIZE - 1)];
+        if is_pv_node {
+            reduction -= 1;
+        }
+        if self.move_orderer.is_killer(depth as usize, chess_move) {
+            reduction -= 1;
+        }
+        if !is_capture && self.move_orderer.get_history(board, chess_move) >= LMR_GOOD_HISTORY {
+            reduction -= 1;
+        }
+        // Always leave at least one ply to search
+        reduction.clamp(0, depth - 2)
+    }
+
+    // Extensions stop once a line is twice as long as the iteration's depth, and can never make the pv outgrow its Line
+    fn can_extend(&self, ply : u32, depth : i32) -> bool {
+        ply < 2 * self.root_depth && (ply + (depth as u32) + 1) < MAX_PV_LENGTH as u32
+    }
+
+    // The tt move is singular if searching every other move, a bit shallower, can't get near its score
+    fn find_singular_move(&mut self, board : &Board, alpha_beta_info : &AlphabetaInfo, entry : &TableEntry, tt : &TranspoTable) -> Option<ChessMove> {
+        let depth = alpha_beta_info.depth_left;
+        if alpha_beta_info.ply == 0 || depth < SINGULAR_MIN_DEPTH || alpha_beta_info.excluded_move.is_some() {
+            return None
+        }
+        if entry.hash != board.get_hash() || (entry.depth as i32) < depth - SINGULAR_TT_DEPTH_MARGIN {
+            return None
+        }
+        // The entry has to be a lower bound at least, and mate scores don't have any margin to work with
+        if matches!(entry.flags, EntryFlags::Alpha) || eval_is_mate(entry.eval) || !board.legal(entry.best_move) {
+            return None
+        }
+        if !self.can_extend(alpha_beta_info.ply, depth) {
+            return None
+        }
+
+        let singular_beta = entry.eval - SINGULAR_MARGIN_PER_DEPTH * depth;
+        let singular_info = AlphabetaInfo {
+            alpha : singular_beta - 1,
+            beta : singular_beta,
+            depth_left : (depth - 1) / 2,
+            ply : alpha_beta_info.ply,
+            last_move : alpha_beta_info.last_move,
+            excluded_move : Some(entry.best_move),
+        };
+        let mut singular_line = Line {
+            cmove : 0,
+            chess_move : [DUMMY_MOVE; MAX_PV_LENGTH],
+        };
+        let eval = self.alphabeta(board, &singular_info, &mut singular_line, tt).eval;
+        if eval < singular_beta && !self.past_end_time {
+            Some(entry.best_move)
+        } else {
+            None
+        }
+    }
+
     fn should_delta_prune(&self, board : &Board, eval : i32, total_material : i32, capture : ChessMove, alpha : i32) -> bool {
+        // Promotions gain too much to prune
+        if capture.get_promotion().is_some() {
+            return false
+        }
         if total_material > DELTA_PRUNE_MATERIAL_CUTOFF {
-            let captured_piece = board.piece_on(capture.get_dest()).unwrap();
-            if eval + NAIVE_PIECE_VAL[captured_piece.to_index()] < alpha {
+            let is_pawn_capture = capture.get_source().get_file() != capture.get_dest().get_file()
+                && board.piece_on(capture.get_source()) == Some(Piece::Pawn);
+            let captured_value = match board.piece_on(capture.get_dest()) {
+                Some(captured_piece) => NAIVE_PIECE_VAL[captured_piece.to_index()],
+                // En passant
+                None if is_pawn_capture => NAIVE_PIECE_VAL[Piece::Pawn.to_index()],
+                // Quiet checks aren't about material
+                None => return false,
+            };
+            if eval + captured_value < alpha {
                 return true
             }
         }
         false
     }
 
-    fn alphabeta(&mut self, board : &Board, alpha_beta_info : &AlphabetaInfo, pv_line : &mut Line, tt : &mut TranspoTable) -> SearchResult {
+    fn alphabeta(&mut self, board : &Board, alpha_beta_info : &AlphabetaInfo, pv_line : &mut Line, tt : &TranspoTable) -> SearchResult {
         // Init variables
         let mut alpha = alpha_beta_info.alpha;
 
         let depth = alpha_beta_info.depth_left;
         let beta = alpha_beta_info.beta;
 
+        // Repetitions and the fifty move rule. The root is never a draw, we still need a move to play from it
+        if alpha_beta_info.ply > 0 && self.history.is_draw(board) {
+            pv_line.cmove = 0;
+            return SearchResult {
+                eval : 0,
+            }
+        }
+
         // Depth 0, quiesce
         if depth <= 0 {
             // As soon as we hit a leaf node, we can't be following the pv any more.
             self.is_following_pv = false;
             pv_line.cmove = 0;
 
-            let eval = self.quiesce(board, alpha_beta_info, tt);
+            // Reductions can take us below 0, quiescence starts counting from 0 itself
+            let quiesce_info = AlphabetaInfo {
+                depth_left : 0,
+                ..*alpha_beta_info
+            };
+            let eval = self.quiesce(board, &quiesce_info, tt);
             self.nodes_evaled += 1;
             return SearchResult {
                 eval: eval,
             }
         }
 
-        // Check our transpo table
+        // Multipv and searchmoves root searches only look at some of the moves, so their result isn't the position's real eval
+        let excluding_root_moves = alpha_beta_info.ply == 0 && (!self.excluded_root_moves.is_empty() || !self.cfg.search_moves.is_empty());
+        // Same for singular extension searches
+        let excluding_moves = excluding_root_moves || alpha_beta_info.excluded_move.is_some();
+
+        // Check our transpo table. The root is always searched, a cutoff there would leave us a one move pv
         let entry = tt.probe(board.get_hash());
-        if entry.hash == board.get_hash() {
-            if entry.depth as i32 >= depth {
-                let mut eval = entry.eval;
-                if self.evaluator.eval_is_mate(eval) {
-                    if eval < 0 {
-                        eval += alpha_beta_info.ply as i32;
-                    } else {
-                        eval -= alpha_beta_info.ply as i32;
-                    }
+        if entry.hash == board.get_hash() && !excluding_moves && alpha_beta_info.ply > 0 && entry.depth as i32 >= depth {
+            let mut eval = entry.eval;
+            if eval_is_mate(eval) {
+                if eval < 0 {
+                    eval += alpha_beta_info.ply as i32;
+                } else {
+                    eval -= alpha_beta_info.ply as i32;
                 }
-                match entry.flags {
-                    EntryFlags::Exact => {
-                        // We know the exact eval.
+            }
+            match entry.flags {
+                EntryFlags::Exact => {
+                    // We know the exact eval.
+                    pv_line.cmove = 1;
+                    pv_line.chess_move[0] = entry.best_move;
+                    return SearchResult {
+                        eval : eval,
+                    }
+                },
+                EntryFlags::Beta => {
+                    // In the past, this node caused a beta cutoff. Check if it would do the same here
+                    if eval >= beta {
                         pv_line.cmove = 1;
                         pv_line.chess_move[0] = entry.best_move;
                         return SearchResult {
-                            eval : eval,
+                            eval : beta
                         }
-                    },
-                    EntryFlags::Beta => {
-                        // In the past, this node caused a beta cutoff. Check if it would do the same here
-                        if eval >= beta {
-                            pv_line.cmove = 1;
-                            pv_line.chess_move[0] = entry.best_move;
-                            return SearchResult {
-                                eval : beta
-                            }
-                        }
-                    },
-                    EntryFlags::Alpha => {
-                        // In the past, we returned alpha for this node, meaning we couldn't beat the lower bound we used to have
-                        // Check if it is worse than our lower bound still
-                        if eval <= alpha {
-                            pv_line.cmove = 1;
-                            pv_line.chess_move[0] = entry.best_move;
-                            return SearchResult {
-                                eval : alpha
-                            }
+                    }
+                },
+                EntryFlags::Alpha => {
+                    // In the past, we returned alpha for this node, meaning we couldn't beat the lower bound we used to have
+                    // Check if it is worse than our lower bound still
+                    if eval <= alpha {
+                        pv_line.cmove = 1;
+                        pv_line.chess_move[0] = entry.best_move;
+                        return SearchResult {
+                            eval : alpha
                         }
-                    },
-                }
+                    }
+                },
             }
         }
 
-        if self.nodes_evaled % 1000 == 0 {
-            if SystemTime::now() > self.end_time {
+        if self.nodes_evaled.is_multiple_of(1000) {
+            self.node_counter.store(self.nodes_evaled, Ordering::Relaxed);
+            self.check_ponder_hit();
+            if self.should_stop() {
                 self.past_end_time = true;
                 return SearchResult {
                     eval : i32::MIN+10,
@@ -383,9 +772,10 @@ impl Search {
         }
 
         // Try out null move pruning
-        if self.should_null_move_prune(board, depth) {
+        if !excluding_moves && self.should_null_move_prune(board, depth) {
             self.in_null_move_prune = true;
             let board_copy = board.null_move().unwrap();
+            self.history.push_null_move(board);
             let reduction_depth: i32 = depth / 4 + NULL_MOVE_MIN_REDUCTION;
             let inner_ab_info: AlphabetaInfo = AlphabetaInfo {
                 alpha : -beta,
@@ -393,13 +783,15 @@ impl Search {
                 depth_left : depth - reduction_depth,
                 ply : alpha_beta_info.ply + 1,
                 last_move : DUMMY_MOVE,
+                excluded_move : None,
             };
             let mut null_move_line: Line = Line {
                 cmove : 0,
-                chess_move : [DUMMY_MOVE; 100],
+                chess_move : [DUMMY_MOVE; MAX_PV_LENGTH],
             };
 
             let result = self.alphabeta(&board_copy, &inner_ab_info, &mut null_move_line, tt);
+            self.history.pop();
             let eval = -result.eval;
             if eval >= beta {
                 return SearchResult {
@@ -412,7 +804,7 @@ impl Search {
 
         // Try to futility prune based on the position
         let position_eval = self.evaluator.eval(board, alpha_beta_info.ply);
-        if self.should_futility_prune_position(board, depth, alpha_beta_info.ply, beta, position_eval) {
+        if !excluding_moves && self.should_futility_prune_position(board, depth, alpha_beta_info.ply, beta, position_eval) {
             return SearchResult {
                 eval : beta
             }
@@ -421,7 +813,7 @@ impl Search {
         // Create our inner line, generate the moves
         let mut line: Line = Line {
             cmove : 0,
-            chess_move : [DUMMY_MOVE; 100],
+            chess_move : [DUMMY_MOVE; MAX_PV_LENGTH],
         };
 
         // TODO Gen psuedo moves, check len(pseudo)
@@ -434,32 +826,81 @@ impl Search {
             alpha = self.evaluator.eval(board, alpha_beta_info.ply);
         }
 
+        let singular_move = self.find_singular_move(board, alpha_beta_info, &entry, tt);
+
         let mut num_alpha_hits = 0;
+        let mut moves_searched = 0;
+        let is_pv_node = beta > alpha + 1;
         // Go through each move, internal alphabeta
         for i in 0..move_ordering.len() {            
             let chess_move = move_ordering.get_next_best_move(i, board, depth as usize, tt, &self.move_orderer, alpha_beta_info.last_move);
+            if excluding_root_moves && self.is_excluded_root_move(chess_move) {
+                continue;
+            }
+            if alpha_beta_info.excluded_move == Some(chess_move) {
+                continue;
+            }
+
+            // test a move
+            let new_board: Board = board.make_move_new(chess_move);
+            let is_move_check = (*new_board.checkers()) != EMPTY;
+
+            // Checks and moves much better than the alternatives are too forcing to judge at the normal depth
+            let mut extension = 0;
+            if (is_move_check || singular_move == Some(chess_move)) && self.can_extend(alpha_beta_info.ply, depth) {
+                extension = 1;
+            }
 
             let inner_ab_info: AlphabetaInfo = AlphabetaInfo {
                 alpha : -beta,
                 beta : -alpha,
-                depth_left : depth - 1,
+                depth_left : depth - 1 + extension,
                 ply : alpha_beta_info.ply + 1,
                 last_move : chess_move,
+                excluded_move : None,
             };
 
-
-            // test a move
-            let new_board: Board = board.make_move_new(chess_move);
-            let is_move_check = (*new_board.checkers()) != EMPTY;
-            if self.should_futility_prune_move(board, is_move_check, chess_move, i, depth, alpha_beta_info.ply, alpha, position_eval) {
+            // The first move is never pruned, so there's always something to search
+            if i > 0 && self.should_futility_prune_move(board, alpha_beta_info, is_move_check, chess_move, alpha, position_eval) {
                 continue;
             }
 
-            let inner_result = self.alphabeta(&new_board, &inner_ab_info, &mut line, tt);
-            let score = -inner_result.eval;
+            self.history.push_move(board, chess_move);
+            let mut score;
+            if moves_searched == 0 {
+                score = -self.alphabeta(&new_board, &inner_ab_info, &mut line, tt).eval;
+            } else {
+                // Principal variation search. Later moves only need to be shown to be worse than alpha, which a null
+                // window does cheaply, and late quiet moves get to show it at a reduced depth.
+                let reduction = self.late_move_reduction(board, is_move_check, chess_move, moves_searched, depth, is_pv_node);
+                let null_window_info = AlphabetaInfo {
+                    alpha : -alpha - 1,
+                    beta : -alpha,
+                    depth_left : depth - 1 + extension - reduction,
+                    ply : alpha_beta_info.ply + 1,
+                    last_move : chess_move,
+                    excluded_move : None,
+                };
+                score = -self.alphabeta(&new_board, &null_window_info, &mut line, tt).eval;
+
+                // The reduced search beat alpha, check it at full depth
+                if score > alpha && reduction > 0 {
+                    let full_depth_info = AlphabetaInfo {
+                        depth_left : depth - 1 + extension,
+                        ..null_window_info
+                    };
+                    score = -self.alphabeta(&new_board, &full_depth_info, &mut line, tt).eval;
+                }
+                // It might be a new best move, get its real score
+                if score > alpha && score < beta {
+                    score = -self.alphabeta(&new_board, &inner_ab_info, &mut line, tt).eval;
+                }
+            }
+            self.history.pop();
+            moves_searched += 1;
             // Score >= beta means refutation was found (i.e we know we worst case eval is -200. this move gives eval of > that)
             if score >= beta {
-                if !self.past_end_time {
+                if !self.past_end_time && !excluding_moves {
                     tt.save(board.get_hash(), beta, EntryFlags::Beta, chess_move, depth as u8, alpha_beta_info.ply as u8);
                     self.move_orderer.update_killer_move(depth as usize, chess_move);
                     
@@ -478,9 +919,8 @@ impl Search {
             // Score > alpha means we have a new best move
             if score > alpha {
                 pv_line.chess_move[0] = chess_move;
-                for i in 1..100 {
-                    pv_line.chess_move[i] = line.chess_move[i - 1];
-                }
+                debug_assert!((line.cmove as usize) < MAX_PV_LENGTH, "pv longer than MAX_PV_LENGTH");
+                pv_line.chess_move[1..].copy_from_slice(&line.chess_move[..MAX_PV_LENGTH - 1]);
                 pv_line.cmove = line.cmove + 1;
                 alpha = score;
 
@@ -488,13 +928,16 @@ impl Search {
             }
         }
         
-        if num_alpha_hits != 0 {
-            // We got the exact eval for the position, not just an alpha lower bound
-            tt.save(board.get_hash(), alpha, EntryFlags::Exact, pv_line.chess_move[0], depth as u8, alpha_beta_info.ply as u8);
-        } else {
-            // We got an alpha lower bound. This means none of the moves were better than the lower bound.
-            // Call the pv move the best
-            tt.save(board.get_hash(), alpha, EntryFlags::Alpha, move_ordering.get(0), depth as u8, alpha_beta_info.ply as u8);
+        // If the search was cut short alpha may not mean anything
+        if !self.past_end_time && !excluding_moves {
+            if num_alpha_hits != 0 {
+                // We got the exact eval for the position, not just an alpha lower bound
+                tt.save(board.get_hash(), alpha, EntryFlags::Exact, pv_line.chess_move[0], depth as u8, alpha_beta_info.ply as u8);
+            } else {
+                // We got an alpha lower bound. This means none of the moves were better than the lower bound.
+                // Call the pv move the best
+                tt.save(board.get_hash(), alpha, EntryFlags::Alpha, move_ordering.get(0), depth as u8, alpha_beta_info.ply as u8);
+            }
         }
 
         SearchResult{
@@ -503,49 +946,94 @@ impl Search {
     }
 
 
+    // Captures, en passant and queen promotions, plus quiet checks if we're at the first ply of quiescence
+    fn generate_quiesce_moves(&self, board : &Board, include_checks : bool) -> MoveOrdering {
+        let side = board.side_to_move();
+        let enemy_pieces = *board.color_combined(!side);
+        let promotion_rank = match side {
+            Color::White => self.bb_utils.rank_mask[7],
+            Color::Black => self.bb_utils.rank_mask[0],
+        };
+        let mut targets = enemy_pieces | promotion_rank;
+        if let Some(en_passant) = board.en_passant() {
+            targets |= BitBoard::from_square(en_passant.uforward(side));
+        }
+
+        let mut moves = MoveGen::new_legal(board);
+        moves.set_iterator_mask(targets);
+        let mut quiesce_moves : Vec<ChessMove> = moves.filter(|chess_move| {
+            match chess_move.get_promotion() {
+                // Underpromotions are almost never better, leave them to the main search
+                Some(piece) => piece == Piece::Queen,
+                // Pieces other than pawns moving onto the back rank
+                None => board.piece_on(chess_move.get_dest()).is_some() || board.piece_on(chess_move.get_source()) == Some(Piece::Pawn),
+            }
+        }).collect();
+
+        if include_checks {
+            let mut quiet_moves = MoveGen::new_legal(board);
+            quiet_moves.set_iterator_mask(!(targets | board.combined()));
+            quiesce_moves.extend(quiet_moves.filter(|chess_move| *board.make_move_new(*chess_move).checkers() != EMPTY));
+        }
+        MoveOrdering::from_move_iter(quiesce_moves.into_iter())
+    }
+
+    // Quiescence has no depth of its own, depth_left counts down from 0 so we know which ply we're on
     fn quiesce(&mut self, board : &Board, alpha_beta_info : &AlphabetaInfo, tt : &TranspoTable) -> i32 {
         // Do our initial eval and check cutoffs
         let mut alpha = alpha_beta_info.alpha;
         let beta = alpha_beta_info.beta;
+        let in_check = *board.checkers() != EMPTY;
+
         let initial_eval = self.evaluator.eval(board, alpha_beta_info.ply);
-        if initial_eval >= beta {
-            return alpha_beta_info.beta
-        }
+        let mut move_ordering;
+        if in_check {
+            // Standing pat isn't an option in check, every evasion has to be searched. No evasions is mate.
+            move_ordering = MoveOrdering::from_moves(&mut MoveGen::new_legal(board));
+            if move_ordering.len() == 0 {
+                return initial_eval
+            }
+        } else {
+            if initial_eval >= beta {
+                return alpha_beta_info.beta
+            }
 
-        // Do initial delta pruning
-        if initial_eval + DELTA_PRUNE_MAX < alpha {
-            return alpha;
-        }
+            // Do initial delta pruning
+            if initial_eval + DELTA_PRUNE_MAX < alpha {
+                return alpha;
+            }
 
-        if initial_eval > alpha {        
-            alpha = initial_eval;
+            if initial_eval > alpha {        
+                alpha = initial_eval;
+            }
+            move_ordering = self.generate_quiesce_moves(board, QUIESCE_CHECKS && alpha_beta_info.depth_left == 0);
         }
 
-        // Do our captures and keep searching
-        // TODO BUG NO ENPASSANT IN THIS
-        let mut moves = MoveGen::new_legal(&board);
-        let targets = board.color_combined(!board.side_to_move());
-        moves.set_iterator_mask(*targets);
-
-        let mut move_ordering = MoveOrdering::from_moves(&mut moves);
         let total_material = self.evaluator.total_material_eval(board);
 
         for i in 0..move_ordering.len() {
-            let capture = move_ordering.get_next_best_move(i, board, 0, tt, &self.move_orderer, alpha_beta_info.last_move);
+            let chess_move = move_ordering.get_next_best_move(i, board, 0, tt, &self.move_orderer, alpha_beta_info.last_move);
 
-            if self.should_delta_prune(board, initial_eval, total_material, capture, alpha) {
-                continue;
+            if !in_check {
+                if self.should_delta_prune(board, initial_eval, total_material, chess_move, alpha) {
+                    continue;
+                }
+                // Losing captures won't improve on standing pat
+                if see(board, chess_move) < 0 {
+                    continue;
+                }
             }
             let inner_ab_info: AlphabetaInfo = AlphabetaInfo {
                 alpha : -beta,
                 beta : -alpha,
-                depth_left : 0,
+                depth_left : alpha_beta_info.depth_left - 1,
                 ply : alpha_beta_info.ply + 1,
-                last_move : capture,
+                last_move : chess_move,
+                excluded_move : None,
             };
 
 
-            let new_board: Board = board.make_move_new(capture);
+            let new_board: Board = board.make_move_new(chess_move);
             let score = -self.quiesce(&new_board, &inner_ab_info, tt);
 
             if score >= beta {
@@ -560,4 +1048,66 @@ impl Search {
         alpha
     }
 
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    // Searches on a thread with the stack the uci loop gives its search threads
+    fn search_position(fen : &str, depth : u32) -> (ChessMove, Vec<ChessMove>) {
+        let board = Board::from_str(fen).unwrap();
+        let mut search = Search::new();
+        search.set_report_info(false);
+        search.set_history(PositionHistory::new(&board, PositionHistory::halfmove_clock_from_fen(fen)));
+        search.set_cfg_depth(depth);
+        thread::Builder::new()
+            .stack_size(SEARCH_THREAD_STACK_SIZE)
+            .spawn(move || {
+                let best_move = search.get_best_move(&board, &TranspoTable::new());
+                (best_move, search.get_pv().to_vec())
+            })
+            .unwrap()
+            .join()
+            .unwrap()
+    }
+
+    #[test]
+    fn search_to_max_depth() {
+        // The fifty move rule ends every line right away, so every iteration up to MAX_DEPTH finishes quickly
+        let (best_move, _) = search_position("8/8/8/8/8/5k2/8/Q3K3 w - - 99 80", MAX_DEPTH);
+        assert_ne!(best_move, DUMMY_MOVE);
+    }
+
+    #[test]
+    fn extended_pv_fits_in_line() {
+        // Queens checking each other, the checks are extended and the pv runs past the search depth
+        let fen = "3Q4/6k1/8/8/8/8/1q4K1/8 w - - 0 1";
+        let depth = 5;
+        let (best_move, pv) = search_position(fen, depth);
+        assert!(pv.len() > depth as usize);
+        assert!(pv.len() <= MAX_PV_LENGTH);
+        assert_eq!(pv[0], best_move);
+        let mut board = Board::from_str(fen).unwrap();
+        for chess_move in pv {
+            assert!(board.legal(chess_move));
+            board = board.make_move_new(chess_move);
+        }
+    }
+
+    #[test]
+    fn pawn_tables_per_thread() {
+        let pawn_tables = Arc::new(Mutex::new(Vec::new()));
+        for num_threads in [4, 1] {
+            let mut search = Search::new();
+            search.set_report_info(false);
+            search.set_pawn_tables(Arc::clone(&pawn_tables));
+            search.set_threads(num_threads);
+            search.set_cfg_depth(3);
+            search.get_best_move(&Board::default(), &TranspoTable::new());
+            // Handed back after the search, and no more of them than there were threads
+            assert_eq!(pawn_tables.lock().unwrap().len(), num_threads);
+        }
+    }
+}
@@ -0,0 +1,132 @@
+// Static exchange evaluation
+// Plays out every capture on a move's destination square, least valuable attacker first, and returns
+// the material the side to move comes out with if both sides only keep capturing while it pays.
+use chess::{BitBoard, Board, ChessMove, Color, Piece, Square, EMPTY, NUM_PIECES,
+    get_bishop_moves, get_king_moves, get_knight_moves, get_pawn_attacks, get_rook_moves};
+
+// The king has to be worth more than anything it could win, but small enough not to overflow the swap list
+const SEE_PIECE_VAL : [i32; NUM_PIECES] = [100, 300, 300, 500, 900, 20000];
+const CHEAPEST_FIRST : [Piece; NUM_PIECES] = [Piece::Pawn, Piece::Knight, Piece::Bishop, Piece::Rook, Piece::Queen, Piece::King];
+// Can't have more captures on one square than there are pieces
+const MAX_EXCHANGES : usize = 33;
+
+// Every piece of both colours attacking square, looking through anything not in occupied
+fn attackers_to(board : &Board, square : Square, occupied : BitBoard) -> BitBoard {
+    let bishops_queens = board.pieces(Piece::Bishop) | board.pieces(Piece::Queen);
+    let rooks_queens = board.pieces(Piece::Rook) | board.pieces(Piece::Queen);
+    let pawns = *board.pieces(Piece::Pawn);
+
+    let attackers = (get_knight_moves(square) & board.pieces(Piece::Knight))
+        | (get_king_moves(square) & board.pieces(Piece::King))
+        | (get_bishop_moves(square, occupied) & bishops_queens)
+        | (get_rook_moves(square, occupied) & rooks_queens)
+        // A white pawn on square would attack exactly the black pawns that attack square, and vice versa
+        | get_pawn_attacks(square, Color::White, pawns & board.color_combined(Color::Black))
+        | get_pawn_attacks(square, Color::Black, pawns & board.color_combined(Color::White));
+    attackers & occupied
+}
+
+fn least_valuable_attacker(board : &Board, attackers : BitBoard) -> Option<(Square, Piece)> {
+    for piece in CHEAPEST_FIRST {
+        let pieces = attackers & board.pieces(piece);
+        if pieces != EMPTY {
+            return Some((pieces.to_square(), piece))
+        }
+    }
+    None
+}
+
+pub fn see(board : &Board, chess_move : ChessMove) -> i32 {
+    let source = chess_move.get_source();
+    let dest = chess_move.get_dest();
+    let mut moving_piece = match board.piece_on(source) {
+        Some(piece) => piece,
+        None => return 0,
+    };
+    let mut occupied = *board.combined() ^ BitBoard::from_square(source);
+
+    let mut gain = [0; MAX_EXCHANGES];
+    gain[0] = match board.piece_on(dest) {
+        Some(piece) => SEE_PIECE_VAL[piece.to_index()],
+        None => 0,
+    };
+    if moving_piece == Piece::Pawn && source.get_file() != dest.get_file() && board.piece_on(dest).is_none() {
+        // The captured pawn isn't on dest, take it off the board
+        gain[0] = SEE_PIECE_VAL[Piece::Pawn.to_index()];
+        occupied ^= BitBoard::from_square(dest.ubackward(board.side_to_move()));
+    }
+    if let Some(promotion) = chess_move.get_promotion() {
+        gain[0] += SEE_PIECE_VAL[promotion.to_index()] - SEE_PIECE_VAL[Piece::Pawn.to_index()];
+        moving_piece = promotion;
+    }
+
+    let mut side = !board.side_to_move();
+    let mut depth = 0;
+    loop {
+        let attackers = attackers_to(board, dest, occupied) & board.color_combined(side);
+        let (square, piece) = match least_valuable_attacker(board, attackers) {
+            Some(attacker) => attacker,
+            None => break,
+        };
+        depth += 1;
+        // What side gets if it takes the piece that just moved to dest
+        gain[depth] = SEE_PIECE_VAL[moving_piece.to_index()] - gain[depth - 1];
+        occupied ^= BitBoard::from_square(square);
+        moving_piece = piece;
+        side = !side;
+    }
+
+    // Each side can stop capturing whenever continuing would lose material
+    while depth > 0 {
+        gain[depth - 1] = -i32::max(-gain[depth - 1], gain[depth]);
+        depth -= 1;
+    }
+    gain[0]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn see_move(fen : &str, source : Square, dest : Square, promotion : Option<Piece>) -> i32 {
+        let board = Board::from_str(fen).unwrap();
+        let chess_move = ChessMove::new(source, dest, promotion);
+        assert!(board.legal(chess_move));
+        see(&board, chess_move)
+    }
+
+    #[test]
+    fn pawn_takes_knight() {
+        assert_eq!(see_move("4k3/8/8/3n4/4P3/8/8/4K3 w - - 0 1", Square::E4, Square::D5, None), 300);
+        // Defended, the pawn is lost for the knight
+        assert_eq!(see_move("4k3/8/4p3/3n4/4P3/8/8/4K3 w - - 0 1", Square::E4, Square::D5, None), 200);
+    }
+
+    #[test]
+    fn queen_takes_defended_pawn() {
+        assert_eq!(see_move("4k3/8/2p5/3p4/8/8/3Q4/4K3 w - - 0 1", Square::D2, Square::D5, None), -800);
+    }
+
+    #[test]
+    fn rook_battery_xray() {
+        // The second rook only joins in once the first has left the file
+        assert_eq!(see_move("4k3/4r3/8/4p3/8/8/4R3/4R1K1 w - - 0 1", Square::E2, Square::E5, None), 100);
+        assert_eq!(see_move("4k3/4r3/8/4p3/8/8/4R3/6K1 w - - 0 1", Square::E2, Square::E5, None), -400);
+        // Queens behind a rook and a bishop, from the chessprogramming wiki
+        assert_eq!(see_move("1k1r3q/1ppn3p/p4b2/4p3/8/P2N2P1/1PP1R1BP/2K1Q3 w - - 0 1", Square::D3, Square::E5, None), -200);
+    }
+
+    #[test]
+    fn en_passant() {
+        assert_eq!(see_move("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1", Square::E5, Square::D6, None), 100);
+        assert_eq!(see_move("4k3/2p5/8/3pP3/8/8/8/4K3 w - d6 0 1", Square::E5, Square::D6, None), 0);
+    }
+
+    #[test]
+    fn promotion_capture() {
+        assert_eq!(see_move("1n2k3/P7/8/8/8/8/8/4K3 w - - 0 1", Square::A7, Square::B8, Some(Piece::Queen)), 1100);
+        // The new queen is taken straight back, we're still a knight for a pawn up
+        assert_eq!(see_move("rn2k3/P7/8/8/8/8/8/4K3 w - - 0 1", Square::A7, Square::B8, Some(Piece::Queen)), 200);
+    }
+}
@@ -0,0 +1,227 @@
+// Decides how long a search may run
+use chess::{ChessMove, Color};
+use std::time::{Duration, SystemTime};
+use vampirc_uci::UciTimeControl;
+
+pub const DEFAULT_MOVE_OVERHEAD_MS : u64 = 10;
+pub const MAX_MOVE_OVERHEAD_MS : u64 = 5000;
+
+// Used when the GUI doesn't send movestogo
+const DEFAULT_MOVES_TO_GO : u32 = 30;
+// Never plan to use more than this fraction of the clock on one move
+const MAX_TIME_FRACTION : f64 = 0.8;
+// The hard limit is this many times the time we'd spend on an average move
+const HARD_LIMIT_FACTOR : u32 = 4;
+// Once past soft * this, an iteration is abandoned unless the score is dropping
+const ABORT_ITERATION_FACTOR : f64 = 2.0;
+// Soft limit scaling by how many iterations in a row the best move stayed the same
+const STABILITY_SCALE : [f64; 5] = [1.3, 1.0, 0.85, 0.7, 0.55];
+// How far the score has to fall between iterations for us to keep thinking
+const SCORE_DROP_MARGIN : i32 = 30;
+
+pub struct TimeManager {
+    start_time : SystemTime,
+    // Don't start another iteration after this
+    soft_limit : Option<Duration>,
+    // Stop searching, even mid iteration, after this
+    hard_limit : Option<Duration>,
+    best_move_stability : usize,
+    last_best_move : Option<ChessMove>,
+    last_eval : Option<i32>,
+    score_dropping : bool,
+}
+
+fn to_std(duration : &Option<vampirc_uci::Duration>) -> Duration {
+    duration.and_then(|duration| duration.to_std().ok()).unwrap_or(Duration::new(0, 0))
+}
+
+impl TimeManager {
+    pub fn infinite() -> TimeManager {
+        TimeManager {
+            start_time : SystemTime::now(),
+            soft_limit : None,
+            hard_limit : None,
+            best_move_stability : 0,
+            last_best_move : None,
+            last_eval : None,
+            score_dropping : false,
+        }
+    }
+
+    pub fn new(time_control : &UciTimeControl, side_to_move : Color, move_overhead : Duration) -> TimeManager {
+        let mut time_manager = TimeManager::infinite();
+        match time_control {
+            UciTimeControl::MoveTime (duration) => {
+                // We were told exactly how long to think, so don't stop early
+                time_manager.hard_limit = Some(to_std(&Some(*duration)).saturating_sub(move_overhead));
+            },
+            UciTimeControl::TimeLeft { white_time, black_time,
+                white_increment, black_increment, moves_to_go } =>
+                {
+                    let (time_left, increment) = match side_to_move {
+                        Color::White => (to_std(white_time), to_std(white_increment)),
+                        Color::Black => (to_std(black_time), to_std(black_increment)),
+                    };
+                    let moves_to_go = match moves_to_go {
+                        Some(moves) if *moves > 0 => *moves as u32,
+                        _ => DEFAULT_MOVES_TO_GO,
+                    };
+
+                    let available = time_left.saturating_sub(move_overhead);
+                    let max_time = available.mul_f64(MAX_TIME_FRACTION);
+                    let hard_limit = Duration::min((available / moves_to_go + increment) * HARD_LIMIT_FACTOR, max_time);
+                    let soft_limit = Duration::min(available / moves_to_go + increment * 3 / 4, hard_limit);
+                    time_manager.soft_limit = Some(soft_limit);
+                    time_manager.hard_limit = Some(hard_limit);
+                },
+            UciTimeControl::Infinite | UciTimeControl::Ponder => (),
+        }
+        time_manager
+    }
+
+    // Pondering searches start counting when the ponder move is played
+    pub fn restart_clock(&mut self) {
+        self.start_time = SystemTime::now();
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.start_time.elapsed().unwrap_or(Duration::new(0, 0))
+    }
+
+    // Checked during the search
+    pub fn should_abort(&self) -> bool {
+        let elapsed = self.elapsed();
+        if let Some(hard_limit) = self.hard_limit {
+            if elapsed >= hard_limit {
+                return true
+            }
+        }
+        // A dropping score means we may be about to find a better move, so use the time up to the hard limit
+        if let Some(soft_limit) = self.soft_limit {
+            if !self.score_dropping && elapsed >= soft_limit.mul_f64(ABORT_ITERATION_FACTOR) {
+                return true
+            }
+        }
+        false
+    }
+
+    // Called after each finished iteration with its result
+    pub fn should_start_iteration(&mut self, best_move : ChessMove, eval : i32) -> bool {
+        if self.last_best_move == Some(best_move) {
+            self.best_move_stability = usize::min(self.best_move_stability + 1, STABILITY_SCALE.len() - 1);
+        } else {
+            self.best_move_stability = 0;
+        }
+        self.score_dropping = self.last_eval.is_some_and(|last_eval| eval < last_eval - SCORE_DROP_MARGIN);
+        self.last_best_move = Some(best_move);
+        self.last_eval = Some(eval);
+
+        match self.soft_limit {
+            Some(soft_limit) => {
+                let mut scale = STABILITY_SCALE[self.best_move_stability];
+                if self.score_dropping {
+                    scale *= 1.5;
+                }
+                self.elapsed() < soft_limit.mul_f64(scale)
+            },
+            None => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chess::Square;
+
+    const OVERHEAD : Duration = Duration::from_millis(10);
+
+    fn ms(millis : i64) -> Option<vampirc_uci::Duration> {
+        Some(vampirc_uci::Duration::milliseconds(millis))
+    }
+
+    fn clock(time_ms : i64, increment_ms : i64, moves_to_go : Option<u8>) -> TimeManager {
+        let time_control = UciTimeControl::TimeLeft {
+            white_time : ms(time_ms),
+            black_time : ms(1),
+            white_increment : ms(increment_ms),
+            black_increment : None,
+            moves_to_go : moves_to_go,
+        };
+        TimeManager::new(&time_control, Color::White, OVERHEAD)
+    }
+
+    #[test]
+    fn limits_fit_in_available_time() {
+        for time_ms in [20, 1000, 10000, 60000, 600000] {
+            for increment_ms in [0, 100, 1000, 5000] {
+                for moves_to_go in [None, Some(1), Some(5), Some(40)] {
+                    let time_manager = clock(time_ms, increment_ms, moves_to_go);
+                    let soft = time_manager.soft_limit.unwrap();
+                    let hard = time_manager.hard_limit.unwrap();
+                    let available = Duration::from_millis(time_ms as u64) - OVERHEAD;
+                    assert!(soft <= hard);
+                    assert!(hard <= available.mul_f64(MAX_TIME_FRACTION));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn moves_to_go_and_sudden_death() {
+        let available = Duration::from_millis(60000) - OVERHEAD;
+        assert_eq!(clock(60000, 0, None).soft_limit, Some(available / DEFAULT_MOVES_TO_GO));
+        assert_eq!(clock(60000, 0, Some(10)).soft_limit, Some(available / 10));
+        // Zero isn't a real movestogo
+        assert_eq!(clock(60000, 0, Some(0)).soft_limit, Some(available / DEFAULT_MOVES_TO_GO));
+        // The last move before the time control can have most of the clock, but not all of it
+        assert_eq!(clock(60000, 0, Some(1)).hard_limit, Some(available.mul_f64(MAX_TIME_FRACTION)));
+    }
+
+    #[test]
+    fn increment() {
+        let without = clock(60000, 0, None);
+        let with = clock(60000, 2000, None);
+        assert_eq!(with.soft_limit.unwrap() - without.soft_limit.unwrap(), Duration::from_millis(1500));
+        assert!(with.hard_limit.unwrap() > without.hard_limit.unwrap());
+        // Black's clock is used for black
+        let time_control = UciTimeControl::TimeLeft {
+            white_time : ms(1), black_time : ms(60000), white_increment : None, black_increment : ms(2000), moves_to_go : None,
+        };
+        assert_eq!(TimeManager::new(&time_control, Color::Black, OVERHEAD).soft_limit, with.soft_limit);
+    }
+
+    #[test]
+    fn move_overhead() {
+        // More overhead than time left leaves nothing, rather than wrapping around
+        let time_manager = clock(5, 0, None);
+        assert_eq!(time_manager.soft_limit, Some(Duration::ZERO));
+        assert_eq!(time_manager.hard_limit, Some(Duration::ZERO));
+        assert!(time_manager.should_abort());
+
+        let move_time = TimeManager::new(&UciTimeControl::MoveTime(vampirc_uci::Duration::milliseconds(100)), Color::White, OVERHEAD);
+        assert_eq!(move_time.hard_limit, Some(Duration::from_millis(90)));
+        assert_eq!(move_time.soft_limit, None);
+        let move_time = TimeManager::new(&UciTimeControl::MoveTime(vampirc_uci::Duration::milliseconds(5)), Color::White, OVERHEAD);
+        assert_eq!(move_time.hard_limit, Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn stability_and_score_drop() {
+        let mut time_manager = TimeManager::infinite();
+        time_manager.soft_limit = Some(Duration::from_millis(1000));
+        time_manager.start_time = SystemTime::now() - Duration::from_millis(900);
+        let best_move = ChessMove::new(Square::E2, Square::E4, None);
+        let other_move = ChessMove::new(Square::D2, Square::D4, None);
+
+        // A new best move gets more time than the soft limit, a stable one less
+        assert!(time_manager.should_start_iteration(best_move, 0));
+        assert!(time_manager.should_start_iteration(best_move, 0));
+        assert!(!time_manager.should_start_iteration(best_move, 0));
+        // The score falling buys more time
+        assert!(time_manager.should_start_iteration(best_move, -SCORE_DROP_MARGIN - 1));
+        assert!(!time_manager.should_start_iteration(best_move, -SCORE_DROP_MARGIN - 1));
+        // And so does the best move changing
+        assert!(time_manager.should_start_iteration(other_move, -SCORE_DROP_MARGIN - 1));
+    }
+}
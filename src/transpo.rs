@@ -1,23 +1,24 @@
 // Our transposition table
+// It's shared between search threads without locking. Each entry is two atomics, and the key is stored
+// xored with the data so an entry torn by two threads writing at once fails the hash check instead of
+// returning another position's data.
 use std::{mem};
-use chess::{ChessMove, Square};
+use std::collections::TryReserveError;
+use std::sync::atomic::{AtomicU64, Ordering};
+use chess::{ChessMove, ALL_PIECES, ALL_SQUARES};
 use crate::evaluation;
 
-const DUMMY_MOVE : ChessMove = ChessMove {
-    source: Square::A1,
-    dest: Square::A1,
-    promotion: None
-};
+pub const DEFAULT_TT_SIZE_MB : u64 = 10;
+pub const MAX_TT_SIZE_MB : u64 = 1048576;
+const BYTES_PER_MB : u64 = 1048576;
 
-const DEFAULT_TABLE_ENTRY : TableEntry = TableEntry {
-    hash : 0,
-    eval : 0,
-    depth : 0,
-    flags : EntryFlags::Exact,
-    best_move : DUMMY_MOVE
-};
-
-const DEFAULT_TT_SIZE : u64 = 1048576 * 10;
+// Layout of the packed data word
+const DEPTH_SHIFT : u64 = 32;
+const FLAGS_SHIFT : u64 = 40;
+const SOURCE_SHIFT : u64 = 42;
+const DEST_SHIFT : u64 = 48;
+const PROMOTION_SHIFT : u64 = 54;
+const SQUARE_MASK : u64 = 0x3f;
 #[derive(Clone, Copy)]
 
 pub enum EntryFlags {
@@ -28,51 +29,109 @@ pub enum EntryFlags {
 #[derive(Clone)]
 pub struct TableEntry {
     pub hash : u64,
-    pub eval : i32, 
+    pub eval : i32,
     pub depth : u8,
     pub flags : EntryFlags,
     pub best_move : ChessMove,
 }
 
+#[derive(Default)]
+struct AtomicEntry {
+    key : AtomicU64,
+    data : AtomicU64,
+}
+
 pub struct TranspoTable {
-    entrys : Vec<TableEntry>
+    entrys : Vec<AtomicEntry>
 }
 
+fn pack(eval : i32, flags : EntryFlags, best_move : ChessMove, depth : u8) -> u64 {
+    let promotion = match best_move.get_promotion() {
+        Some(piece) => piece.to_index() as u64 + 1,
+        None => 0,
+    };
+    (eval as u32 as u64)
+        | (depth as u64) << DEPTH_SHIFT
+        | (flags as u64) << FLAGS_SHIFT
+        | (best_move.get_source().to_index() as u64) << SOURCE_SHIFT
+        | (best_move.get_dest().to_index() as u64) << DEST_SHIFT
+        | promotion << PROMOTION_SHIFT
+}
+
+fn unpack(hash : u64, data : u64) -> TableEntry {
+    let flags = match (data >> FLAGS_SHIFT) & 0x3 {
+        1 => EntryFlags::Alpha,
+        2 => EntryFlags::Beta,
+        _ => EntryFlags::Exact,
+    };
+    let promotion = match (data >> PROMOTION_SHIFT) & 0x7 {
+        0 => None,
+        piece => Some(ALL_PIECES[piece as usize - 1]),
+    };
+    let source = ALL_SQUARES[((data >> SOURCE_SHIFT) & SQUARE_MASK) as usize];
+    let dest = ALL_SQUARES[((data >> DEST_SHIFT) & SQUARE_MASK) as usize];
+    TableEntry {
+        hash : hash,
+        eval : data as u32 as i32,
+        depth : (data >> DEPTH_SHIFT) as u8,
+        flags : flags,
+        best_move : ChessMove::new(source, dest, promotion),
+    }
+}
 
 impl TranspoTable {
     pub fn new() -> TranspoTable {
         let mut tt = TranspoTable { entrys: Vec::new()};
-        tt.set_size(DEFAULT_TT_SIZE);
+        tt.set_size_mb(DEFAULT_TT_SIZE_MB).expect("Failed to allocate the transposition table");
         tt
     }
 
-    fn set_size(&mut self, size : u64) {
-        
-        let calc_entrys = u64::max((size / mem::size_of::<TableEntry>() as u64) - 1, 0);
-        self.entrys = vec![DEFAULT_TABLE_ENTRY; calc_entrys as usize];
+    // Reallocates the table, throwing away everything in it. If there isn't enough memory the table keeps its old size
+    pub fn set_size_mb(&mut self, size_mb : u64) -> Result<(), TryReserveError> {
+        let size = size_mb * BYTES_PER_MB;
+        let calc_entrys = u64::max(size / mem::size_of::<AtomicEntry>() as u64, 1) as usize;
+        let old_entrys = usize::max(self.entrys.len(), 1);
+        // Free the old table first so both are never allocated at once
+        self.entrys = Vec::new();
+        let result = self.entrys.try_reserve_exact(calc_entrys);
+        let num_entrys = match result {
+            Ok(()) => calc_entrys,
+            Err(_) => old_entrys,
+        };
+        self.entrys.resize_with(num_entrys, AtomicEntry::default);
+        result
     }
 
-    pub fn probe(&self, key : u64) -> &TableEntry {
-        return &self.entrys[(key % self.entrys.len() as u64) as usize];
+    pub fn clear(&mut self) {
+        for entry in self.entrys.iter_mut() {
+            *entry = AtomicEntry::default();
+        }
     }
 
-    pub fn save(&mut self, key : u64, eval : i32, flags : EntryFlags, best_move : ChessMove, depth : u8, ply : u8) {
+    // The returned entry's hash won't match key if the slot holds another position
+    pub fn probe(&self, key : u64) -> TableEntry {
+        let entry = &self.entrys[(key % self.entrys.len() as u64) as usize];
+        let data = entry.data.load(Ordering::Relaxed);
+        let hash = entry.key.load(Ordering::Relaxed) ^ data;
+        unpack(hash, data)
+    }
+
+    pub fn save(&self, key : u64, eval : i32, flags : EntryFlags, best_move : ChessMove, depth : u8, ply : u8) {
         let len = self.entrys.len();
-        let mut entry = &mut self.entrys[(key % len as u64) as usize];
-        if depth >= entry.depth {    
-            entry.hash = key;
+        let entry = &self.entrys[(key % len as u64) as usize];
+        let old_depth = (entry.data.load(Ordering::Relaxed) >> DEPTH_SHIFT) as u8;
+        if depth >= old_depth {
+            let mut eval = eval;
             if evaluation::eval_is_mate(eval) {
                 if eval < 0 {
-                    entry.eval = eval - ply as i32;
+                    eval -= ply as i32;
                 } else {
-                    entry.eval = eval + ply as i32;
+                    eval += ply as i32;
                 }
-            } else {
-                entry.eval = eval;
             }
-            entry.depth = depth;
-            entry.best_move = best_move;
-            entry.flags = flags;
+            let data = pack(eval, flags, best_move, depth);
+            entry.key.store(key ^ data, Ordering::Relaxed);
+            entry.data.store(data, Ordering::Relaxed);
         }
     }
-}
\ No newline at end of file
+}
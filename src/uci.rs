@@ -1,73 +1,245 @@
-use vampirc_uci::{UciMessage};
+use vampirc_uci::{UciMessage, UciTimeControl};
 use std::io::{self, BufRead};
 use std::str::FromStr;
-use std::time::SystemTime;
-use chess::{Board, MoveGen};
-use crate::evaluation::Evaluator;
-use crate::search::{Search};
-use crate::transpo;
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use chess::Board;
+use artemis::bench;
+use artemis::book::Book;
+use artemis::evaluation::Evaluator;
+use artemis::history::PositionHistory;
+use artemis::perft;
+use artemis::search::{Search, MAX_MULTI_PV, MAX_THREADS, SEARCH_THREAD_STACK_SIZE};
+use artemis::time_management::{DEFAULT_MOVE_OVERHEAD_MS, MAX_MOVE_OVERHEAD_MS};
+use artemis::transpo;
 
 const ARTEMIS_VERSION : &str = "1.0";
 
+// Signal a running search to stop and wait for it to print its bestmove
+fn stop_search(search_thread : &mut Option<JoinHandle<()>>, stop : &Arc<AtomicBool>) {
+    if let Some(handle) = search_thread.take() {
+        stop.store(true, Ordering::Relaxed);
+        handle.join().expect("Search thread panicked");
+    }
+}
+
 pub fn uci_loop () {
     let mut board = Board::default();
-    let mut tt = transpo::TranspoTable::new();
+    let mut history = PositionHistory::new(&board, 0);
+    // Searches only need shared access, the write lock is for resizing and clearing between searches
+    let tt = Arc::new(RwLock::new(transpo::TranspoTable::new()));
+    // Pawn tables for each search thread, handed from one search to the next
+    let pawn_tables = Arc::new(Mutex::new(Vec::new()));
+    let mut num_threads : usize = 1;
+    let mut multi_pv : usize = 1;
+    let mut move_overhead : u64 = DEFAULT_MOVE_OVERHEAD_MS;
+    let stop = Arc::new(AtomicBool::new(false));
+    let pondering = Arc::new(AtomicBool::new(false));
+    let mut search_thread : Option<JoinHandle<()>> = None;
+    let mut own_book = false;
+    let mut book : Option<Book> = None;
     println!("Artemis {ARTEMIS_VERSION}");
     'outer: loop {
         for line in io::stdin().lock().lines() {
-            let msg: UciMessage = vampirc_uci::parse_one(&line.unwrap());
+            let line = line.unwrap();
+            let msg: UciMessage = vampirc_uci::parse_one(&line);
 
             match msg {
                 UciMessage::UciNewGame => {
+                    stop_search(&mut search_thread, &stop);
                     board = Board::default();
+                    history = PositionHistory::new(&board, 0);
+                },
+                UciMessage::Quit => {
+                    stop_search(&mut search_thread, &stop);
+                    break 'outer
+                },
+                UciMessage::Stop => {
+                    stop_search(&mut search_thread, &stop);
+                },
+                UciMessage::PonderHit => {
+                    pondering.store(false, Ordering::Relaxed);
                 },
-                UciMessage::Quit => break 'outer,
                 UciMessage::Position { startpos, fen, moves } => {
+                    stop_search(&mut search_thread, &stop);
+                    let mut halfmove_clock = 0;
                     if startpos {
                         board = Board::default();
                     }
                     if fen.is_some() {
-                        board = Board::from_str(fen.unwrap().as_str()).unwrap(); 
+                        let fen = fen.unwrap();
+                        board = Board::from_str(fen.as_str()).unwrap(); 
+                        halfmove_clock = PositionHistory::halfmove_clock_from_fen(fen.as_str());
                     }
 
+                    history = PositionHistory::new(&board, halfmove_clock);
                     for chess_move in moves {
+                        history.push_move(&board, chess_move);
                         board = board.make_move_new(chess_move);
                     }
 
                 },
                 
                 UciMessage::Go { time_control, search_control } => {
-                    let mut search = Search::new();
+                    stop_search(&mut search_thread, &stop);
+                    stop.store(false, Ordering::Relaxed);
+                    // The parser drops ponder when the clock times are also given, so look for it ourselves
+                    pondering.store(line.split_whitespace().any(|word| word == "ponder"), Ordering::Relaxed);
 
-                    if search_control.is_some() {
-                        let control = search_control.unwrap();
-                        if control.depth.is_some() {
-                            search.set_cfg_depth(control.depth.unwrap() as u32);
+                    // Book moves are played without searching, except when the GUI expects us to think until told to stop
+                    let is_infinite = matches!(time_control, Some(UciTimeControl::Infinite));
+                    if own_book && !is_infinite && !pondering.load(Ordering::Relaxed) {
+                        let book_move = book.as_mut().and_then(|book| book.pick_move(&board));
+                        if let Some(book_move) = book_move {
+                            let allowed = search_control.as_ref().is_none_or(|control|
+                                control.search_moves.is_empty() || control.search_moves.contains(&book_move));
+                            if allowed {
+                                println!("bestmove {book_move}");
+                                continue;
+                            }
                         }
                     }
-                    if time_control.is_some() {
-                        let control = time_control.unwrap();
-                        search.set_time_controls(control);
+                    let mut search = Search::new();
+                    search.set_stop_flag(Arc::clone(&stop));
+                    search.set_pawn_tables(Arc::clone(&pawn_tables));
+                    search.set_ponder_flag(Arc::clone(&pondering));
+                    search.set_history(history.clone());
+                    search.set_threads(num_threads);
+                    search.set_multi_pv(multi_pv);
+                    search.set_move_overhead(Duration::from_millis(move_overhead));
+
+                    if let Some(search_control) = search_control {
+                        search.set_search_controls(search_control);
+                    }
+                    if let Some(time_control) = time_control {
+                        search.set_time_controls(time_control);
                     }
-                    let result = search.get_best_move(&board, &mut tt);
+                    let search_board = board;
+                    let search_tt = Arc::clone(&tt);
+                    search_thread = Some(thread::Builder::new()
+                        .stack_size(SEARCH_THREAD_STACK_SIZE)
+                        .spawn(move || {
+                            let tt = search_tt.read().unwrap();
+                            let result = search.get_best_move(&search_board, &tt);
 
-                    println!("bestmove {result}");
+                            match search.get_ponder_move() {
+                                Some(ponder_move) => println!("bestmove {result} ponder {ponder_move}"),
+                                None => println!("bestmove {result}"),
+                            }
+                        })
+                        .expect("Failed to spawn search thread"));
 
                 },
+                UciMessage::SetOption { name, value } => {
+                    stop_search(&mut search_thread, &stop);
+                    match name.to_lowercase().as_str() {
+                        "hash" => {
+                            match value.as_deref().map(str::parse::<u64>) {
+                                Some(Ok(size_mb)) => {
+                                    let size_mb = size_mb.clamp(1, transpo::MAX_TT_SIZE_MB);
+                                    if tt.write().unwrap().set_size_mb(size_mb).is_err() {
+                                        println!("info string Not enough memory for {size_mb} MB of Hash, keeping the old size");
+                                    }
+                                },
+                                _ => println!("info string Invalid Hash value"),
+                            }
+                        },
+                        "threads" => {
+                            match value.as_deref().map(str::parse::<usize>) {
+                                Some(Ok(threads)) => num_threads = threads.clamp(1, MAX_THREADS),
+                                _ => println!("info string Invalid Threads value"),
+                            }
+                        },
+                        "multipv" => {
+                            match value.as_deref().map(str::parse::<usize>) {
+                                Some(Ok(num_pvs)) => multi_pv = num_pvs.clamp(1, MAX_MULTI_PV),
+                                _ => println!("info string Invalid MultiPV value"),
+                            }
+                        },
+                        "move overhead" => {
+                            match value.as_deref().map(str::parse::<u64>) {
+                                Some(Ok(overhead)) => move_overhead = overhead.min(MAX_MOVE_OVERHEAD_MS),
+                                _ => println!("info string Invalid Move Overhead value"),
+                            }
+                        },
+                        "ownbook" => {
+                            match value.as_deref().map(str::parse::<bool>) {
+                                Some(Ok(use_book)) => own_book = use_book,
+                                _ => println!("info string Invalid OwnBook value"),
+                            }
+                        },
+                        "bookfile" => {
+                            book = None;
+                            match value.as_deref() {
+                                Some(path) if !path.is_empty() && path != "<empty>" => {
+                                    match Book::load(path) {
+                                        Ok(loaded) => {
+                                            println!("info string Loaded book {path} with {} entries", loaded.size());
+                                            book = Some(loaded);
+                                        },
+                                        Err(error) => println!("info string Failed to load book {path}: {error}"),
+                                    }
+                                },
+                                _ => (),
+                            }
+                        },
+                        // Only tells us whether the GUI may send go ponder, which we always support
+                        "ponder" => (),
+                        "clear hash" => {
+                            tt.write().unwrap().clear();
+                        },
+                        _ => println!("info string Unknown option {name}"),
+                    }
+                },
                 UciMessage::IsReady => {
                     println!("readyok");
                 },
                 UciMessage::Uci => {
                     println!("id name Artemis Release {ARTEMIS_VERSION}");
                     println!("id author Stuart Nevans Locke");
-                    // println!("option name Hash type spin default 32 min 1 max 1048576");
+                    println!("option name Hash type spin default {} min 1 max {}", transpo::DEFAULT_TT_SIZE_MB, transpo::MAX_TT_SIZE_MB);
+                    println!("option name Clear Hash type button");
+                    println!("option name Threads type spin default 1 min 1 max {MAX_THREADS}");
+                    println!("option name Ponder type check default false");
+                    println!("option name Move Overhead type spin default {DEFAULT_MOVE_OVERHEAD_MS} min 0 max {MAX_MOVE_OVERHEAD_MS}");
+                    println!("option name MultiPV type spin default 1 min 1 max {MAX_MULTI_PV}");
+                    println!("option name OwnBook type check default false");
+                    println!("option name BookFile type string default <empty>");
                     println!("uciok");
                 },
-                UciMessage::UciNewGame => {
-                    board = Board::default();
-                },
-                UciMessage::CopyProtection ( _ ) => {
-                    perft(&board, 3);
+                // Our own commands, vampirc doesn't know them
+                UciMessage::Unknown ( .. ) => {
+                    let words : Vec<&str> = line.split_whitespace().collect();
+                    match words.as_slice() {
+                        ["perft", depth] | ["go", "perft", depth] => {
+                            match depth.parse::<u32>() {
+                                Ok(depth) => {
+                                    stop_search(&mut search_thread, &stop);
+                                    perft::divide(&board, depth);
+                                },
+                                Err(_) => println!("info string Invalid perft depth"),
+                            }
+                        },
+                        ["bench"] => {
+                            stop_search(&mut search_thread, &stop);
+                            bench::bench(bench::DEFAULT_BENCH_DEPTH);
+                        },
+                        ["bench", depth] => {
+                            match depth.parse::<u32>() {
+                                Ok(depth) if depth > 0 => {
+                                    stop_search(&mut search_thread, &stop);
+                                    bench::bench(depth);
+                                },
+                                _ => println!("info string Invalid bench depth"),
+                            }
+                        },
+                        ["eval"] => {
+                            print!("{}", Evaluator::new().trace(&board));
+                        },
+                        _ => (),
+                    }
                 },
 
 
@@ -76,37 +248,3 @@ pub fn uci_loop () {
         }
     }
 }
-
-fn perft(board : &Board, depth : i32) {
-    println!("perft");
-    // create an iterable
-    let iterable = MoveGen::new_legal(&board);
-    let mut total = 0;
-    let start = SystemTime::now();
-    for chess_move in iterable {
-        let board_copy = board.make_move_new(chess_move);
-        let num = perft_internal(&board_copy, depth - 1);
-        println!("{chess_move} {num}", );
-        total += num;
-    }
-    let duration = SystemTime::now().duration_since(start).expect("Error");
-
-    println!("-----------------");
-    println!("Nodes: {total}");
-    println!("Took {}s. nps {}", duration.as_millis() as f64 / 1000.0, (total as f64 / (duration.as_millis() as f64 / 1000.0)));
-}
-
-fn perft_internal(board : &Board, depth : i32) -> i32 {
-    if depth == 0 {
-        Evaluator::new().eval(board,0);
-        return 1
-    }
-    let iterable = MoveGen::new_legal(&board);
-    let mut total = 0;
-    for chess_move in iterable {
-        let board_copy = board.make_move_new(chess_move);
-        let num = perft_internal(&board_copy, depth - 1);
-        total += num;
-    }
-    total
-}
\ No newline at end of file
@@ -0,0 +1,47 @@
+use artemis::{Engine, SearchLimits};
+use chess::{Board, ChessMove, Square};
+use std::str::FromStr;
+
+#[test]
+fn search_from_startpos() {
+    let mut engine = Engine::new();
+    let info = engine.search(&SearchLimits { nodes : Some(20000), ..Default::default() });
+    assert!(Board::default().legal(info.best_move));
+    assert_eq!(info.pv.first(), Some(&info.best_move));
+    assert!(info.nodes > 0);
+}
+
+#[test]
+fn fixed_depth_finds_mate() {
+    let mut engine = Engine::new();
+    engine.set_position(Some("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1"), &[]).unwrap();
+    let info = engine.search(&SearchLimits { depth : Some(3), ..Default::default() });
+    assert_eq!(info.best_move, ChessMove::new(Square::A1, Square::A8, None));
+    assert!(info.score > 0);
+}
+
+#[test]
+fn set_position_plays_moves() {
+    let mut engine = Engine::new();
+    let moves = [ChessMove::new(Square::E2, Square::E4, None), ChessMove::new(Square::E7, Square::E5, None)];
+    engine.set_position(None, &moves).unwrap();
+    let expected = Board::from_str("rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 0 2").unwrap();
+    assert_eq!(engine.board().get_hash(), expected.get_hash());
+
+    // From a fen, and an illegal move leaves the position alone
+    let fen = "4k3/8/8/8/8/8/4P3/4K3 w - - 0 1";
+    engine.set_position(Some(fen), &[ChessMove::new(Square::E2, Square::E4, None)]).unwrap();
+    assert_eq!(engine.board().piece_on(Square::E4), Some(chess::Piece::Pawn));
+    let before = engine.board().get_hash();
+    assert!(engine.set_position(Some(fen), &[ChessMove::new(Square::E2, Square::E5, None)]).is_err());
+    assert_eq!(engine.board().get_hash(), before);
+}
+
+#[test]
+fn nodes_from_every_thread() {
+    let mut engine = Engine::default();
+    engine.set_threads(4);
+    let info = engine.search(&SearchLimits { depth : Some(6), ..Default::default() });
+    assert!(Board::default().legal(info.best_move));
+    assert!(info.nodes > 0);
+}